@@ -0,0 +1,88 @@
+//! Clone and lookup cost of storing accounts owned vs behind an `Arc`, across
+//! data sizes. Run with `cargo bench --bench account_storage`.
+//!
+//! smolchain is a binary crate, so this can't import its `Account`; the
+//! struct below has the same fields.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const DATA_SIZES: &[usize] = &[0, 128, 10 * 1024];
+const ACCOUNTS: u64 = 1_000;
+const ROUNDS: usize = 100;
+
+#[allow(dead_code)]
+#[derive(Clone, Default)]
+struct Account {
+    balance: u64,
+    owner: u64,
+    tokens: BTreeMap<u64, u64>,
+    data: Vec<u8>,
+}
+
+fn account(account_id: u64, data_len: usize) -> Account {
+    Account {
+        balance: account_id,
+        data: vec![0; data_len],
+        ..Default::default()
+    }
+}
+
+/// Time `f` over every id, `ROUNDS` times.
+fn time(mut f: impl FnMut(u64)) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for account_id in 0..ACCOUNTS {
+            f(account_id);
+        }
+    }
+
+    start.elapsed()
+}
+
+fn report(name: &str, data_len: usize, elapsed: Duration) {
+    let ops = ROUNDS as u128 * ACCOUNTS as u128;
+    println!(
+        "{name:<14} data_len {data_len:>6}: {:>8} ns/op",
+        elapsed.as_nanos() / ops
+    );
+}
+
+fn main() {
+    for &data_len in DATA_SIZES {
+        let owned: HashMap<u64, Account> = (0..ACCOUNTS)
+            .map(|account_id| (account_id, account(account_id, data_len)))
+            .collect();
+        let shared: HashMap<u64, Arc<Account>> = owned
+            .iter()
+            .map(|(account_id, account)| (*account_id, Arc::new(account.clone())))
+            .collect();
+
+        report(
+            "clone owned",
+            data_len,
+            time(|account_id| drop(black_box(owned[&account_id].clone()))),
+        );
+        report(
+            "clone arc",
+            data_len,
+            time(|account_id| drop(black_box(shared[&account_id].clone()))),
+        );
+        report(
+            "lookup owned",
+            data_len,
+            time(|account_id| {
+                black_box(owned.get(&black_box(account_id)).map(|a| a.balance));
+            }),
+        );
+        report(
+            "lookup arc",
+            data_len,
+            time(|account_id| {
+                black_box(shared.get(&black_box(account_id)).map(|a| a.balance));
+            }),
+        );
+    }
+}
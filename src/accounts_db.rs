@@ -3,6 +3,11 @@
 //! keeps track of any per-fork updates. `AccountsDb` stores these
 //! `VersionedAccount`s in a `DashMap` so that we can access them in parallel.
 //!
+//! Each `VersionedAccount` sits behind its own lock, so a transaction can hold
+//! several accounts at once even when they hash to the same `DashMap` shard.
+//! Inside the lock it's held by `Arc` and copied on write, which lets
+//! `snapshot_handle` capture the whole map without deep-cloning it.
+//!
 //! Anytime a fork makes an update to an account, we add the update to the
 //! `VersionedAccount`'s `inflight_updates` queue. When a fork is rooted
 //! (i.e., reaches economic finality) `AccountsDb` flushes its
@@ -12,264 +17,3063 @@
 
 use super::*;
 
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::ops::{Deref, DerefMut};
 
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock, RwLockReadGuard, Weak};
+
+use dashmap::{DashMap, DashSet, RawRwLock, SharedValue};
+use lock_api::{ArcRwLockReadGuard, ArcRwLockWriteGuard};
+
+mod snapshot;
+
+mod wal;
+use wal::Wal;
+pub use wal::WalEntry;
+
+mod subscription;
+use subscription::SubscriptionBuffer;
+pub use subscription::{OverflowPolicy, Subscription};
+
+type AccountLock = Arc<lock_api::RwLock<RawRwLock, Arc<VersionedAccount>>>;
+pub type ReadAccount = ArcRwLockReadGuard<RawRwLock, Arc<VersionedAccount>>;
+
+/// A write-locked `VersionedAccount`. Mutating through it un-shares the
+/// account from any snapshot that still references it.
+pub struct WriteAccount<'a> {
+    account_id: AccountId,
+    guard: ArcRwLockWriteGuard<RawRwLock, Arc<VersionedAccount>>,
+    accounts_db: &'a AccountsDb,
+    // slots to publish to the WAL and subscribers on drop, just before the
+    // lock is released
+    written_slots: Vec<Slot>,
+    // which of those slots the account was created in, for the WAL
+    created_slots: Vec<Slot>,
+}
+
+/// How many accounts exist on each fork, kept as the number on the rooted
+/// chain plus the number created in each inflight slot. `load_account` knows
+/// the fork it's writing to, so it counts exactly; `set_account` only knows
+/// the slot, and counts an account as created there if the db has never
+/// seen it before.
+#[derive(Clone, Default, Debug)]
+struct AccountCounts {
+    finalized: u64,
+    created: BTreeMap<Slot, u64>,
+}
+
+impl AccountCounts {
+    fn add_created(&mut self, slot: Slot) {
+        *self.created.entry(slot).or_default() += 1;
+    }
+
+    fn remove_created(&mut self, slot: Slot) {
+        if let Some(created) = self.created.get_mut(&slot) {
+            *created = created.saturating_sub(1);
+        }
+    }
+}
+
+fn genesis_account() -> Account {
+    Account {
+        balance: GENESIS_SUPPLY,
+        owner: 0,
+        ..Default::default()
+    }
+}
+
+// what `DashMap::with_shard_amount` accepts without panicking
+fn valid_shard_amount(shard_amount: usize) -> bool {
+    shard_amount > 1 && shard_amount.is_power_of_two()
+}
+
+/// Where each recorded transaction landed, for `finality_latency`.
+#[derive(Default, Debug)]
+struct FinalityLog {
+    // transactions not rooted yet, with every slot they were applied in
+    inflight: HashMap<TransactionId, Vec<Slot>>,
+    // applied slot and the root that finalized it
+    finalized: HashMap<TransactionId, (Slot, Slot)>,
+}
+
+impl WriteAccount<'_> {
+    /// `VersionedAccount::load_account`, recording the slot written. An
+    /// account that doesn't exist on this fork yet starts out as the db's
+    /// configured `default_account`.
+    pub fn load_account(&mut self, slots_to_include: &[Slot]) -> Result<&mut Account, LoadError> {
+        if let Some(slot) = slots_to_include.last() {
+            self.record_write(*slot);
+
+            if self.get_account(slots_to_include).is_none() {
+                self.record_created(*slot);
+
+                let default_account = self.accounts_db.default_account().clone();
+                self.deref_mut().set_account(default_account, *slot);
+            }
+        }
+
+        self.deref_mut().load_account(slots_to_include)
+    }
+
+    /// `VersionedAccount::set_account`, recording the slot written. An
+    /// account with no versions at all counts as created in `slot`.
+    pub fn set_account(&mut self, account: Account, slot: Slot) {
+        self.record_write(slot);
+        if self.finalized_acc.is_none() && self.inflight_updates.is_empty() {
+            self.record_created(slot);
+        }
+
+        self.deref_mut().set_account(account, slot)
+    }
+
+    /// `VersionedAccount::discard_update` for the last slot in
+    /// `slots_to_include`, no longer counting the account if that update is
+    /// what created it on this fork.
+    pub fn discard_update(&mut self, slots_to_include: &[Slot]) -> Option<Account> {
+        let slot = *slots_to_include.last()?;
+        let account = self.deref_mut().discard_update(slot)?;
+        if self.get_account(slots_to_include).is_none() {
+            self.accounts_db
+                .account_counts
+                .lock()
+                .unwrap()
+                .remove_created(slot);
+        }
+
+        Some(account)
+    }
+
+    fn record_created(&mut self, slot: Slot) {
+        self.accounts_db
+            .account_counts
+            .lock()
+            .unwrap()
+            .add_created(slot);
+        if self.accounts_db.wal.is_some() {
+            self.created_slots.push(slot);
+        }
+    }
+
+    fn record_write(&mut self, slot: Slot) {
+        self.accounts_db
+            .max_observed_slot
+            .fetch_max(slot, Ordering::Relaxed);
+
+        let published = self.accounts_db.wal.is_some()
+            || self
+                .accounts_db
+                .subscriptions
+                .contains_key(&self.account_id);
+        if published && !self.written_slots.contains(&slot) {
+            self.written_slots.push(slot);
+        }
+    }
+}
+
+impl Drop for WriteAccount<'_> {
+    fn drop(&mut self) {
+        for slot in &self.written_slots {
+            let Some((_, account)) = self
+                .guard
+                .inflight_updates
+                .iter()
+                .rev()
+                .find(|(update_slot, _)| update_slot == slot)
+            else {
+                continue;
+            };
+
+            if let Some(wal) = &self.accounts_db.wal {
+                wal.append(WalEntry {
+                    slot: *slot,
+                    account_id: self.account_id,
+                    account: account.clone(),
+                    created: self.created_slots.contains(slot),
+                });
+            }
+            self.accounts_db.publish(self.account_id, *slot, account);
+        }
+    }
+}
+
+impl Deref for WriteAccount<'_> {
+    type Target = VersionedAccount;
+
+    fn deref(&self) -> &VersionedAccount {
+        &self.guard
+    }
+}
+
+impl DerefMut for WriteAccount<'_> {
+    fn deref_mut(&mut self) -> &mut VersionedAccount {
+        Arc::make_mut(&mut self.guard)
+    }
+}
+
+/// Write locks held for the duration of one transaction. Loading an account a
+/// second time hands back the account already loaded, so a transaction always
+/// reads its own writes.
+///
+/// Accounts that might not exist yet have to be named up front: creating one
+/// takes a write lock on its accounts map shard, which must never happen
+/// while this context already holds account locks.
+pub struct TransactionContext<'a> {
+    accounts_db: &'a AccountsDb,
+    ancestors: &'a [Slot],
+    accounts: Vec<(AccountId, WriteAccount<'a>)>,
+}
 
-use dashmap::mapref::one::{Ref, RefMut};
-use dashmap::try_result::TryResult;
-use dashmap::DashMap;
+impl<'a> TransactionContext<'a> {
+    /// Creates any of `account_ids` that don't exist yet, before any lock
+    /// is taken. Only these accounts may be created by `load` later on.
+    pub fn new(
+        accounts_db: &'a AccountsDb,
+        ancestors: &'a [Slot],
+        account_ids: &[AccountId],
+    ) -> Result<Self, LoadError> {
+        accounts_db.initialize_empty_batch(account_ids)?;
+        Ok(Self {
+            accounts_db,
+            ancestors,
+            accounts: Vec::new(),
+        })
+    }
+
+    pub fn load(&mut self, account_id: AccountId) -> Result<&mut Account, LoadError> {
+        let index = match self.accounts.iter().position(|(id, _)| *id == account_id) {
+            Some(index) => index,
+            None => {
+                if !self.accounts.is_empty() && !self.accounts_db.contains(account_id) {
+                    return Err(LoadError::OneOrMoreAccountsLocked);
+                }
+                let (_, mut write_accounts) = self
+                    .accounts_db
+                    .load_versioned_accounts(&[], &[account_id])?;
+                self.accounts.push((account_id, write_accounts.remove(0)));
+                self.accounts.len() - 1
+            }
+        };
+
+        self.accounts[index].1.load_account(self.ancestors)
+    }
+}
+
+/// Read and write locks taken together by `AccountsDb::lock_accounts`, with
+/// accounts looked up by id and resolved against one fork. Everything is
+/// released when the guard drops.
+pub struct LockGuard<'a> {
+    ancestors: &'a [Slot],
+    read_accounts: Vec<(AccountId, ReadAccount)>,
+    write_accounts: Vec<(AccountId, WriteAccount<'a>)>,
+}
+
+impl LockGuard<'_> {
+    /// `account_id` as this fork sees it, if the guard holds it at all.
+    pub fn read(&self, account_id: AccountId) -> Option<&Account> {
+        let write_account = self
+            .write_accounts
+            .iter()
+            .find(|(id, _)| *id == account_id)
+            .map(|(_, write_account)| &**write_account);
+        let versioned_account = write_account.or_else(|| {
+            self.read_accounts
+                .iter()
+                .find(|(id, _)| *id == account_id)
+                .map(|(_, read_account)| &***read_account)
+        })?;
+
+        versioned_account.get_account(self.ancestors)
+    }
+
+    /// `account_id` for writing on this fork, starting from the default
+    /// account if it doesn't exist yet.
+    pub fn write(&mut self, account_id: AccountId) -> Result<&mut Account, LoadError> {
+        let (_, write_account) = self
+            .write_accounts
+            .iter_mut()
+            .find(|(id, _)| *id == account_id)
+            .ok_or(LoadError::AccountNotLocked(account_id))?;
+
+        write_account.load_account(self.ancestors)
+    }
+}
+
+/// The order `load_versioned_accounts_with_order` takes locks in. Locks are
+/// only ever tried, never waited on, so any order is deadlock free; the
+/// choice only changes which conflicting load gives up first.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AcquireOrder {
+    /// By account id, reads and writes interleaved.
+    #[default]
+    Ascending,
+    /// Reads then writes, each in the order given.
+    AsGiven,
+    /// Writes then reads, each by account id.
+    WritesFirst,
+    /// By accounts map shard, then by account id.
+    ByShard,
+}
+
+/// What a call to `AccountsDb::finalize` did with the inflight updates at or
+/// below the new root.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FinalizeReport {
+    /// Updates on the finalized chain that became an account's rooted version.
+    pub promoted: usize,
+    /// Updates off the finalized chain that were dropped.
+    pub pruned: usize,
+    /// Accounts that had at least one update promoted or pruned.
+    pub accounts_touched: usize,
+}
+
+/// How often `load_versioned_accounts` found an account already locked,
+/// bucketed by the accounts map shard the account lives in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContentionStats {
+    pub per_shard: Vec<u64>,
+}
 
-#[derive(Debug)]
+impl ContentionStats {
+    pub fn total(&self) -> u64 {
+        self.per_shard.iter().sum()
+    }
+}
+
+/// A point-in-time copy of an `AccountsDb` that shares unchanged accounts with
+/// the live db.
+pub struct SnapshotHandle {
+    finalized_slot: Slot,
+    account_counts: AccountCounts,
+    accounts: Vec<(AccountId, Arc<VersionedAccount>)>,
+}
+
+#[derive(Debug, PartialEq)]
 pub enum LoadError {
     OneOrMoreAccountsLocked,
+    NoSlotsToInclude,
+    ImmutableAccount,
+    AccountLimitReached,
+    /// `LockGuard::write` on an account the guard doesn't hold for writing.
+    AccountNotLocked(AccountId),
+}
+
+/// Where a `VersionedAccount::get_account` lookup found its answer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resolution {
+    Inflight(Slot),
+    Finalized,
+    /// Neither an included inflight update nor a finalized version.
+    Missing,
+}
+
+/// How a lookup resolved; see `VersionedAccount::explain`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResolutionTrace {
+    pub resolution: Resolution,
+    /// Inflight slots passed over because they weren't included, newest
+    /// first, as the lookup scans them.
+    pub skipped: Vec<Slot>,
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct VersionedAccount {
     finalized_acc: Option<Account>,
     inflight_updates: VecDeque<(Slot, Account)>,
+    // bumped whenever any version of the account may have changed
+    version: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AccountsDbConfig {
+    /// Refuse to create accounts past this many. The check happens before
+    /// inserting, so racing creators can overshoot it slightly.
+    pub max_accounts: Option<usize>,
+    /// Shard count for the accounts map, which must be a power of two greater
+    /// than 1: `from_genesis_config` rejects anything else, and the other
+    /// constructors panic. Defaults to `DashMap`'s own choice.
+    pub shard_amount: Option<usize>,
+    /// What `WriteAccount::load_account` starts an account from when it has
+    /// no prior version.
+    pub default_account: Account,
+}
+
+/// Holds the root still while it's alive; see `AccountsDb::read_barrier`.
+pub struct ReadBarrier<'a> {
+    _root: RwLockReadGuard<'a, ()>,
+}
+
+/// The accounts a db starts with; see `AccountsDb::from_genesis_config`.
+#[derive(Clone, Debug)]
+pub struct GenesisConfig {
+    /// What the native balances of `allocations` must add up to.
+    pub supply: u64,
+    pub allocations: Vec<(AccountId, Account)>,
+}
+
+impl Default for GenesisConfig {
+    /// The whole `GENESIS_SUPPLY` in account 0.
+    fn default() -> Self {
+        Self {
+            supply: GENESIS_SUPPLY,
+            allocations: vec![(0, genesis_account())],
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GenesisError {
+    SupplyMismatch {
+        supply: u64,
+        allocated: u128,
+    },
+    DuplicateAccount(AccountId),
+    /// `AccountsDbConfig::shard_amount` isn't a power of two greater than 1.
+    InvalidShardAmount(usize),
 }
 
 pub struct AccountsDb {
+    config: AccountsDbConfig,
     finalized_slot: AtomicU64,
-    accounts: DashMap<AccountId, VersionedAccount>,
+    max_observed_slot: AtomicU64,
+    // added to every account's own version, and raised past all of them
+    // whenever older versions are put back, so `account_version` never repeats
+    version_floor: AtomicU64,
+    account_counts: Mutex<AccountCounts>,
+    // the chain passed to the last `finalize_fast`, until `sweep` runs
+    pending_sweep: Mutex<Option<Vec<Slot>>>,
+    finality_log: Mutex<FinalityLog>,
+    // held for reading by each `ReadBarrier` and for writing while the root
+    // moves
+    root_lock: RwLock<()>,
+    wal: Option<Wal>,
+    subscriptions: DashMap<AccountId, Vec<Weak<SubscriptionBuffer>>>,
+    accounts: DashMap<AccountId, AccountLock>,
+    // failed account lock attempts, indexed by the shard the account is in
+    contention: Box<[AtomicU64]>,
+    // successful account lock attempts, indexed the same way
+    acquisitions: Box<[AtomicU64]>,
+    // accounts that can only be written through `set_sysvar`
+    pinned: DashSet<AccountId>,
 }
 
 impl VersionedAccount {
     pub fn get_account(&self, slots_to_include: &[Slot]) -> Option<&Account> {
+        self.get_account_ex(slots_to_include, true)
+    }
+
+    /// `get_account`, optionally without falling back to the finalized
+    /// version, so `None` means the account hasn't changed on this fork since
+    /// it was last rooted.
+    pub fn get_account_ex(
+        &self,
+        slots_to_include: &[Slot],
+        include_finalized: bool,
+    ) -> Option<&Account> {
         for (slot, account) in self.inflight_updates.iter().rev() {
-            if slots_to_include.contains(&slot) {
-                return Some(&account);
+            if slots_to_include.contains(slot) {
+                return Some(account);
             }
         }
 
-        self.finalized_acc.as_ref()
+        self.finalized_acc.as_ref().filter(|_| include_finalized)
     }
 
-    pub fn load_account(&mut self, slots_to_include: &[Slot]) -> &mut Account {
-        let current_slot = *slots_to_include.last().unwrap();
+    /// Walk the same lookup as `get_account` and report what it resolved to
+    /// and which inflight updates it passed over on the way.
+    pub fn explain(&self, slots_to_include: &[Slot]) -> ResolutionTrace {
+        let mut skipped = Vec::new();
+        for (slot, _) in self.inflight_updates.iter().rev() {
+            if slots_to_include.contains(slot) {
+                return ResolutionTrace {
+                    resolution: Resolution::Inflight(*slot),
+                    skipped,
+                };
+            }
+            skipped.push(*slot);
+        }
 
-        if self.inflight_updates.len() > 0
-            && self
-                .inflight_updates
-                .get(self.inflight_updates.len() - 1)
-                .unwrap()
-                .0
-                == current_slot
-        {
-        } else if let Some((_, account)) = self
+        let resolution = match self.finalized_acc {
+            Some(_) => Resolution::Finalized,
+            None => Resolution::Missing,
+        };
+        ResolutionTrace {
+            resolution,
+            skipped,
+        }
+    }
+
+    /// `get_account`, but skips the inflight scan when every slot in
+    /// `slots_to_include` is at or below `finalized_slot` and no inflight
+    /// update is, since then only `finalized_acc` can match.
+    pub fn get_account_with_root(
+        &self,
+        slots_to_include: &[Slot],
+        finalized_slot: Slot,
+    ) -> Option<&Account> {
+        let fully_rooted = slots_to_include
+            .last()
+            .is_some_and(|tip| *tip <= finalized_slot);
+        let no_rooted_updates = self
             .inflight_updates
-            .iter()
-            .rev()
-            .find(|(slot, _)| slots_to_include.contains(slot))
-        {
-            self.inflight_updates
-                .push_back((current_slot, account.clone()));
-        } else if let Some(finalized_acc) = &self.finalized_acc {
-            self.inflight_updates
-                .push_back((current_slot, finalized_acc.clone()));
-        } else {
-            self.inflight_updates
-                .push_back((current_slot, Account::default()));
+            .front()
+            .is_none_or(|(slot, _)| *slot > finalized_slot);
+
+        if fully_rooted && no_rooted_updates {
+            return self.finalized_acc.as_ref();
         }
 
-        &mut self.inflight_updates.back_mut().unwrap().1
+        self.get_account(slots_to_include)
     }
 
-    pub fn set_account(&mut self, account: Account, slot: Slot) {
-        if self.inflight_updates.len() > 0 {
-            let last_inflight_update = self.inflight_updates.back_mut().unwrap();
-            if last_inflight_update.0 == slot {
-                last_inflight_update.1 = account;
-                return;
+    /// Return the account as of the last slot in `slots_to_include`, creating
+    /// an inflight update for that slot if there isn't one yet. Once that
+    /// update exists, later calls for the same slot return it as-is, so
+    /// writes are visible to every subsequent load in the slot.
+    pub fn load_account(&mut self, slots_to_include: &[Slot]) -> Result<&mut Account, LoadError> {
+        let current_slot = *slots_to_include.last().ok_or(LoadError::NoSlotsToInclude)?;
+        // the caller gets a `&mut`, so assume it writes
+        self.version += 1;
+
+        let index = match self.update_index(current_slot) {
+            Ok(index) => index,
+            Err(index) => {
+                let account = self
+                    .get_account(slots_to_include)
+                    .cloned()
+                    .unwrap_or_default();
+                self.inflight_updates.insert(index, (current_slot, account));
+                index
             }
-        }
-        self.inflight_updates.push_back((slot, account));
+        };
+
+        Ok(&mut self.inflight_updates[index].1)
     }
-}
 
-impl AccountsDb {
-    pub fn genesis_database() -> Self {
-        let accounts_db = AccountsDb {
-            finalized_slot: AtomicU64::new(0),
-            accounts: DashMap::new(),
-        };
-        accounts_db.accounts.insert(
-            0,
-            VersionedAccount {
-                finalized_acc: Some(Account {
-                    balance: GENESIS_SUPPLY,
-                }),
-                inflight_updates: VecDeque::new(),
-            },
-        );
+    pub fn set_account(&mut self, account: Account, slot: Slot) {
+        self.version += 1;
+        match self.update_index(slot) {
+            Ok(index) => self.inflight_updates[index].1 = account,
+            Err(index) => self.inflight_updates.insert(index, (slot, account)),
+        }
+    }
 
-        accounts_db
+    /// Where the update for `slot` is, or where it would go to keep
+    /// `inflight_updates` in slot order. Writes can arrive out of slot order,
+    /// e.g. when a bank for an older sibling slot is created late.
+    fn update_index(&self, slot: Slot) -> Result<usize, usize> {
+        self.inflight_updates
+            .binary_search_by_key(&slot, |(update_slot, _)| *update_slot)
     }
 
-    pub fn initialize_empty_versioned_account(&self, account_id: AccountId) {
-        self.accounts.insert(
-            account_id,
-            VersionedAccount {
-                finalized_acc: None,
-                inflight_updates: VecDeque::new(),
-            },
-        );
+    /// The inflight update with the highest slot on any fork, for inspection
+    /// only: nothing says that slot is an ancestor of anything in particular.
+    pub fn peek_latest(&self) -> Option<(Slot, &Account)> {
+        self.inflight_updates
+            .back()
+            .map(|(slot, account)| (*slot, account))
     }
 
-    pub fn get_versioned_account(
-        &self,
-        account_id: AccountId,
-    ) -> Option<Ref<AccountId, VersionedAccount>> {
-        self.accounts.get(&account_id)
+    /// A counter that goes up with every write to the account on any fork,
+    /// so a cached copy is stale once this moves past the value seen with it.
+    pub fn version(&self) -> u64 {
+        self.version
     }
 
-    pub fn load_versioned_accounts(
-        &self,
-        read_account_ids: &[AccountId],
-        write_account_ids: &[AccountId],
-    ) -> Result<
-        (
-            Vec<Ref<AccountId, VersionedAccount>>,
-            Vec<RefMut<AccountId, VersionedAccount>>,
-        ),
-        LoadError,
-    > {
-        let mut read_accounts = Vec::new();
-        let mut write_accounts = Vec::new();
-
-        for account_id in [read_account_ids, write_account_ids].concat() {
-            if !self.accounts.contains_key(&account_id) {
-                self.initialize_empty_versioned_account(account_id);
-            }
-        }
-
-        for account_id in read_account_ids {
-            let try_result = self.accounts.try_get(account_id);
-
-            match try_result {
-                TryResult::Locked => return Err(LoadError::OneOrMoreAccountsLocked),
-                TryResult::Absent => unreachable!(),
-                TryResult::Present(account) => {
-                    read_accounts.push(account);
-                }
+    /// Drop each inflight update on the chain `ancestors` that's equal to what
+    /// the chain resolved to just before it, and return how many went. Any
+    /// fork that includes such an update shares the chain up to it, so every
+    /// fork resolves the same as before.
+    pub fn compact(&mut self, ancestors: &[Slot]) -> usize {
+        let mut previous = self.finalized_acc.as_ref();
+        let mut redundant = Vec::new();
+        for (index, (slot, account)) in self.inflight_updates.iter().enumerate() {
+            if !ancestors.contains(slot) {
+                continue;
+            }
+            if previous == Some(account) {
+                redundant.push(index);
+            } else {
+                previous = Some(account);
             }
         }
 
-        for account_id in write_account_ids {
-            let try_result = self.accounts.try_get_mut(account_id);
-
-            match try_result {
-                TryResult::Locked => return Err(LoadError::OneOrMoreAccountsLocked),
-                TryResult::Absent => unreachable!(),
-                TryResult::Present(account) => {
-                    write_accounts.push(account);
-                }
-            }
+        for index in redundant.iter().rev() {
+            self.inflight_updates.remove(*index);
         }
 
-        Ok((read_accounts, write_accounts))
+        redundant.len()
     }
 
-    pub fn finalize(&self, slots: &[Slot]) {
-        let tip = *slots.last().unwrap();
-        let finalized_slot = self.finalized_slot.load(Ordering::Relaxed);
+    /// Whether there's an inflight update written in exactly `slot`.
+    pub fn has_update_in(&self, slot: Slot) -> bool {
+        self.update_index(slot).is_ok()
+    }
 
-        if tip <= finalized_slot {
-            return;
-        }
+    /// Drop the inflight update written in exactly `slot`, if there is one.
+    pub fn discard_update(&mut self, slot: Slot) -> Option<Account> {
+        let index = self
+            .inflight_updates
+            .iter()
+            .position(|(update_slot, _)| *update_slot == slot)?;
+        self.version += 1;
 
-        self.accounts.iter_mut().for_each(|mut versioned_account| {
-            while let Some((update_slot, account)) = versioned_account.inflight_updates.pop_front()
-            {
-                if update_slot <= tip {
-                    if slots.contains(&update_slot) {
-                        versioned_account.finalized_acc = Some(account);
-                    }
-                } else {
-                    versioned_account
-                        .inflight_updates
-                        .push_front((update_slot, account));
-                    break;
-                }
+        self.inflight_updates
+            .remove(index)
+            .map(|(_, account)| account)
+    }
+
+    /// Check that `inflight_updates` is ordered by strictly increasing slot,
+    /// which both lookups and `finalize` rely on.
+    pub fn verify(&self) -> Result<(), String> {
+        for (prev, next) in self
+            .inflight_updates
+            .iter()
+            .zip(self.inflight_updates.iter().skip(1))
+        {
+            if prev.0 >= next.0 {
+                return Err(format!(
+                    "inflight update for slot {} follows slot {}",
+                    next.0, prev.0
+                ));
             }
-        });
+        }
 
-        self.finalized_slot.store(tip, Ordering::Relaxed);
+        Ok(())
     }
 }
 
-#[cfg(test)]
-pub mod tests {
-    use super::*;
+impl AccountsDb {
+    fn empty(finalized_slot: Slot, config: AccountsDbConfig) -> Self {
+        let accounts = Self::accounts_map(&config);
 
-    #[test]
-    fn test_genesis_database() {
-        let accounts_db = AccountsDb::genesis_database();
-        assert_eq!(accounts_db.accounts.len(), 1);
-        assert_eq!(
-            accounts_db
-                .accounts
-                .get(&0)
-                .unwrap()
-                .finalized_acc
-                .as_ref()
-                .unwrap()
-                .balance,
-            GENESIS_SUPPLY
-        );
+        Self::with_accounts(finalized_slot, config, accounts)
     }
 
-    #[test]
-    fn test_initialize_empty_versioned_account() {
-        let accounts_db = AccountsDb::genesis_database();
-        accounts_db.initialize_empty_versioned_account(1);
-        assert_eq!(accounts_db.accounts.len(), 2);
+    fn accounts_map(config: &AccountsDbConfig) -> DashMap<AccountId, AccountLock> {
+        match config.shard_amount {
+            Some(shard_amount) => {
+                assert!(
+                    valid_shard_amount(shard_amount),
+                    "shard_amount must be a power of two greater than 1, got {shard_amount}"
+                );
+                DashMap::with_shard_amount(shard_amount)
+            }
+            None => DashMap::new(),
+        }
     }
 
-    #[test]
-    fn test_load_versioned_accounts() {
-        let accounts_db = AccountsDb::genesis_database();
-        accounts_db.initialize_empty_versioned_account(1);
-        accounts_db.initialize_empty_versioned_account(2);
-        accounts_db.initialize_empty_versioned_account(3);
+    /// A db around an already built accounts map, all of it finalized at
+    /// `finalized_slot`.
+    fn with_accounts(
+        finalized_slot: Slot,
+        config: AccountsDbConfig,
+        accounts: DashMap<AccountId, AccountLock>,
+    ) -> Self {
+        let account_counts = AccountCounts {
+            finalized: accounts.len() as u64,
+            created: BTreeMap::new(),
+        };
+        let contention = accounts
+            .shards()
+            .iter()
+            .map(|_| AtomicU64::new(0))
+            .collect();
+        let acquisitions = accounts
+            .shards()
+            .iter()
+            .map(|_| AtomicU64::new(0))
+            .collect();
 
-        {
-            let (read_accounts, mut write_accounts) = accounts_db
-                .load_versioned_accounts(&[0, 1], &[2, 3])
-                .expect("load");
+        AccountsDb {
+            config,
+            finalized_slot: AtomicU64::new(finalized_slot),
+            max_observed_slot: AtomicU64::new(finalized_slot),
+            version_floor: AtomicU64::new(0),
+            account_counts: Mutex::new(account_counts),
+            pending_sweep: Mutex::default(),
+            finality_log: Mutex::default(),
+            root_lock: RwLock::default(),
+            wal: None,
+            subscriptions: DashMap::new(),
+            accounts,
+            contention,
+            acquisitions,
+            pinned: DashSet::new(),
+        }
+    }
 
-            assert_eq!(read_accounts.len(), 2);
-            assert_eq!(write_accounts.len(), 2);
+    pub fn genesis_database() -> Self {
+        Self::genesis_database_with_config(AccountsDbConfig::default())
+    }
 
-            assert_eq!(
-                read_accounts[0].get_account(&[0]).unwrap().balance,
-                GENESIS_SUPPLY
-            );
-            assert_eq!(write_accounts[0].load_account(&[0]).balance, 0);
+    pub fn genesis_database_with_config(config: AccountsDbConfig) -> Self {
+        Self::from_genesis_config(GenesisConfig::default(), config)
+            .expect("default genesis is valid")
+    }
 
-            let (from_slice, to_slice) = write_accounts.split_at_mut(1);
+    /// A db holding exactly `genesis`'s allocations, finalized at slot 0.
+    /// The config is checked in full before any state is built.
+    pub fn from_genesis_config(
+        genesis: GenesisConfig,
+        config: AccountsDbConfig,
+    ) -> Result<Self, GenesisError> {
+        if let Some(shard_amount) = config.shard_amount {
+            if !valid_shard_amount(shard_amount) {
+                return Err(GenesisError::InvalidShardAmount(shard_amount));
+            }
+        }
+        let mut allocated: u128 = 0;
+        let mut state = BTreeMap::new();
+        for (account_id, account) in genesis.allocations {
+            allocated += u128::from(account.balance);
+            if state.insert(account_id, account).is_some() {
+                return Err(GenesisError::DuplicateAccount(account_id));
+            }
+        }
+        if allocated != u128::from(genesis.supply) {
+            return Err(GenesisError::SupplyMismatch {
+                supply: genesis.supply,
+                allocated,
+            });
+        }
 
-            let from = &mut from_slice[0];
-            let to = &mut to_slice[0];
+        Ok(Self::from_state_with_config(state, config))
+    }
 
-            let from = from.load_account(&[0]);
-            let to = to.load_account(&[0]);
+    /// Throw away every account and all fork and finality bookkeeping, leaving
+    /// just the genesis account finalized at slot 0. Config and pinned ids are
+    /// kept, so sysvars stay immutable once they're written again. The WAL is
+    /// emptied, so replaying it onto genesis matches the db again. Meant for
+    /// test harnesses between cases; nothing else should be using the db
+    /// while this runs.
+    pub fn reset_to_genesis(&self) {
+        let _root = self.root_lock.write().unwrap();
+        self.raise_version_floor();
+        self.accounts.clear();
+        self.accounts.insert(
+            0,
+            Arc::new(lock_api::RwLock::new(Arc::new(VersionedAccount {
+                finalized_acc: Some(genesis_account()),
+                inflight_updates: VecDeque::new(),
+                version: 0,
+            }))),
+        );
 
-            from.balance = 10;
-            to.balance = 15;
+        *self.account_counts.lock().unwrap() = AccountCounts {
+            finalized: 1,
+            created: BTreeMap::new(),
+        };
+        *self.pending_sweep.lock().unwrap() = None;
+        *self.finality_log.lock().unwrap() = FinalityLog::default();
+        if let Some(wal) = &self.wal {
+            wal.clear();
         }
+        self.finalized_slot.store(0, Ordering::Relaxed);
+        self.max_observed_slot.store(0, Ordering::Relaxed);
+    }
 
-        assert_eq!(
-            accounts_db
-                .accounts
-                .get(&3)
-                .unwrap()
-                .get_account(&[0])
-                .unwrap()
-                .balance,
-            15
-        );
+    /// A db whose accounts are exactly `state`, all finalized at slot 0.
+    pub fn from_state(state: BTreeMap<AccountId, Account>) -> Self {
+        Self::from_state_with_config(state, AccountsDbConfig::default())
+    }
+
+    pub fn from_state_with_config(
+        state: BTreeMap<AccountId, Account>,
+        config: AccountsDbConfig,
+    ) -> Self {
+        // fill the map before there's a db to observe it
+        let accounts = Self::accounts_map(&config);
+        for (account_id, account) in state {
+            accounts.insert(
+                account_id,
+                Arc::new(lock_api::RwLock::new(Arc::new(VersionedAccount {
+                    finalized_acc: Some(account),
+                    inflight_updates: VecDeque::new(),
+                    version: 0,
+                }))),
+            );
+        }
+
+        Self::with_accounts(0, config, accounts)
+    }
+
+    pub fn initialize_empty_versioned_account(
+        &self,
+        account_id: AccountId,
+    ) -> Result<(), LoadError> {
+        self.account_lock(account_id).map(drop)
+    }
+
+    /// Insert an empty versioned account for each of `account_ids` that
+    /// doesn't have one yet, locking each accounts map shard at most twice
+    /// rather than once per id. Fails without inserting anything if that
+    /// would go past `max_accounts`.
+    pub fn initialize_empty_batch(&self, account_ids: &[AccountId]) -> Result<(), LoadError> {
+        // Loads of existing accounts are the common case, so don't group
+        // them by shard when there is nothing to create.
+        if account_ids
+            .iter()
+            .all(|account_id| self.accounts.contains_key(account_id))
+        {
+            return Ok(());
+        }
+
+        let mut by_shard: BTreeMap<usize, Vec<AccountId>> = BTreeMap::new();
+        for account_id in account_ids {
+            by_shard
+                .entry(self.accounts.determine_map(account_id))
+                .or_default()
+                .push(*account_id);
+        }
+
+        let shards = self.accounts.shards();
+        for account_ids in by_shard.values_mut() {
+            let shard = shards[self.accounts.determine_map(&account_ids[0])].read();
+            account_ids.retain(|account_id| !shard.contains_key(account_id));
+            drop(shard);
+            account_ids.sort_unstable();
+            account_ids.dedup();
+        }
+        by_shard.retain(|_, account_ids| !account_ids.is_empty());
+        if by_shard.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(max_accounts) = self.config.max_accounts {
+            let missing: usize = by_shard.values().map(Vec::len).sum();
+            if self.accounts.len() + missing > max_accounts {
+                return Err(LoadError::AccountLimitReached);
+            }
+        }
+
+        for (shard, account_ids) in by_shard {
+            let mut shard = shards[shard].write();
+            shard.reserve(account_ids.len());
+            for account_id in account_ids {
+                shard
+                    .entry(account_id)
+                    .or_insert_with(|| SharedValue::new(AccountLock::default()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Insert an empty versioned account for each of `account_ids` up front,
+    /// so loads don't have to create them under contention later. Stops at
+    /// the first id past `max_accounts`.
+    pub fn preallocate(
+        &self,
+        account_ids: impl Iterator<Item = AccountId>,
+    ) -> Result<(), LoadError> {
+        for account_id in account_ids {
+            self.initialize_empty_versioned_account(account_id)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn contains(&self, account_id: AccountId) -> bool {
+        self.accounts.contains_key(&account_id)
+    }
+
+    pub fn finalized_slot(&self) -> Slot {
+        self.finalized_slot.load(Ordering::Relaxed)
+    }
+
+    /// The highest slot ever written through a `WriteAccount`.
+    pub fn max_observed_slot(&self) -> Slot {
+        self.max_observed_slot.load(Ordering::Relaxed)
+    }
+
+    pub fn get_versioned_account(&self, account_id: AccountId) -> Option<ReadAccount> {
+        let account_lock = self.accounts.get(&account_id)?.clone();
+
+        Some(account_lock.read_arc())
+    }
+
+    /// `account_id`'s rooted version, ignoring every inflight update.
+    pub fn finalized_account(&self, account_id: AccountId) -> Option<Account> {
+        self.get_versioned_account(account_id)?
+            .finalized_acc
+            .clone()
+    }
+
+    /// Resolve each of `account_ids` against `ancestors`. Every lock is
+    /// released as soon as its account is cloned, so none are held on return.
+    pub fn read_many(&self, account_ids: &[AccountId], ancestors: &[Slot]) -> Vec<Option<Account>> {
+        account_ids
+            .iter()
+            .map(|account_id| {
+                self.get_versioned_account(*account_id)?
+                    .get_account(ancestors)
+                    .cloned()
+            })
+            .collect()
+    }
+
+    /// Every inflight update written on the fork made of `ancestors`, i.e.
+    /// what rooting that fork would promote, sorted by account id then slot.
+    pub fn fork_updates(&self, ancestors: &[Slot]) -> Vec<(AccountId, Slot, Account)> {
+        let mut updates: Vec<(AccountId, Slot, Account)> = self
+            .account_locks()
+            .into_iter()
+            .flat_map(|(account_id, account_lock)| {
+                account_lock
+                    .read()
+                    .inflight_updates
+                    .iter()
+                    .filter(|(slot, _)| ancestors.contains(slot))
+                    .map(|(slot, account)| (account_id, *slot, account.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        updates.sort_by_key(|(account_id, slot, _)| (*account_id, *slot));
+
+        updates
+    }
+
+    /// A rough measure of how much value is riding on unrooted forks: the sum
+    /// of each account's latest inflight balance, whatever fork it's on.
+    /// Pinned sysvars are left out.
+    pub fn inflight_value(&self) -> u64 {
+        self.account_locks()
+            .into_iter()
+            .filter(|(account_id, _)| !self.pinned.contains(account_id))
+            .filter_map(|(_, account_lock)| {
+                let versioned_account = account_lock.read();
+                versioned_account
+                    .inflight_updates
+                    .back()
+                    .map(|(_, account)| account.balance)
+            })
+            .sum()
+    }
+
+    /// Every account with an inflight update tagged exactly `slot`, i.e. what
+    /// that slot's block changed, sorted by id.
+    pub fn accounts_modified_in(&self, slot: Slot) -> Vec<AccountId> {
+        self.accounts_modified_in_any(&[slot])
+    }
+
+    /// Every account with an inflight update in one of `slots`, sorted by id.
+    pub fn accounts_modified_in_any(&self, slots: &[Slot]) -> Vec<AccountId> {
+        let mut account_ids: Vec<AccountId> = self
+            .account_locks()
+            .into_iter()
+            .filter(|(_, account_lock)| {
+                account_lock
+                    .read()
+                    .inflight_updates
+                    .iter()
+                    .any(|(slot, _)| slots.contains(slot))
+            })
+            .map(|(account_id, _)| account_id)
+            .collect();
+        account_ids.sort_unstable();
+
+        account_ids
+    }
+
+    /// Whether every account with an inflight update in exactly `slot` is one
+    /// of `account_ids`, pinned accounts aside. `account_ids` themselves aren't
+    /// looked at, so the caller may hold their locks; any other account that's
+    /// locked right now fails with `OneOrMoreAccountsLocked`.
+    pub fn slot_writes_within(
+        &self,
+        slot: Slot,
+        account_ids: &[AccountId],
+    ) -> Result<bool, LoadError> {
+        for (account_id, account_lock) in self.account_locks() {
+            if account_ids.contains(&account_id) || self.pinned.contains(&account_id) {
+                continue;
+            }
+            let versioned_account = account_lock
+                .try_read()
+                .ok_or(LoadError::OneOrMoreAccountsLocked)?;
+            if versioned_account.has_update_in(slot) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// The finalized version of every account that has one, in ascending id
+    /// order. Inflight updates are ignored.
+    pub fn finalized_sorted(&self) -> impl Iterator<Item = (AccountId, Account)> {
+        let mut accounts = self.scan_accounts(&[]);
+        accounts.sort_unstable_by_key(|(account_id, _)| *account_id);
+
+        accounts.into_iter()
+    }
+
+    /// Resolve every account against `ancestors`, skipping those that don't
+    /// exist on that fork.
+    pub fn scan_accounts(&self, ancestors: &[Slot]) -> Vec<(AccountId, Account)> {
+        self.account_locks()
+            .into_iter()
+            .filter_map(|(account_id, account_lock)| {
+                let account = account_lock.read().get_account(ancestors).cloned()?;
+                Some((account_id, account))
+            })
+            .collect()
+    }
+
+    /// A handle to every account's lock. Scans go through this rather than
+    /// locking accounts while iterating the map: a shard stays read-locked for
+    /// as long as an iterator is in it, and waiting on an account lock in
+    /// there deadlocks against a writer that holds that account and wants the
+    /// shard.
+    fn account_locks(&self) -> Vec<(AccountId, AccountLock)> {
+        self.accounts
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect()
+    }
+
+    fn account_lock(&self, account_id: AccountId) -> Result<AccountLock, LoadError> {
+        if let Some(account_lock) = self.accounts.get(&account_id) {
+            return Ok(account_lock.clone());
+        }
+
+        if let Some(max_accounts) = self.config.max_accounts {
+            if self.accounts.len() >= max_accounts {
+                return Err(LoadError::AccountLimitReached);
+            }
+        }
+
+        Ok(self.accounts.entry(account_id).or_default().clone())
+    }
+
+    /// Try to lock `read_account_ids` for reading and `write_account_ids` for
+    /// writing, taking the locks in ascending id order. Fails rather than
+    /// waits if any of them is already locked.
+    pub fn load_versioned_accounts(
+        &self,
+        read_account_ids: &[AccountId],
+        write_account_ids: &[AccountId],
+    ) -> Result<(Vec<ReadAccount>, Vec<WriteAccount<'_>>), LoadError> {
+        self.load_versioned_accounts_with_order(
+            read_account_ids,
+            write_account_ids,
+            AcquireOrder::default(),
+        )
+    }
+
+    /// `load_versioned_accounts`, wrapped in a `LockGuard` that resolves
+    /// accounts against `ancestors`.
+    pub fn lock_accounts<'a>(
+        &'a self,
+        ancestors: &'a [Slot],
+        read_account_ids: &[AccountId],
+        write_account_ids: &[AccountId],
+    ) -> Result<LockGuard<'a>, LoadError> {
+        let (read_accounts, write_accounts) =
+            self.load_versioned_accounts(read_account_ids, write_account_ids)?;
+
+        Ok(LockGuard {
+            ancestors,
+            read_accounts: read_account_ids
+                .iter()
+                .copied()
+                .zip(read_accounts)
+                .collect(),
+            write_accounts: write_account_ids
+                .iter()
+                .copied()
+                .zip(write_accounts)
+                .collect(),
+        })
+    }
+
+    /// `load_versioned_accounts`, taking the locks in `order`. Either way the
+    /// returned accounts are in the order they were asked for.
+    pub fn load_versioned_accounts_with_order(
+        &self,
+        read_account_ids: &[AccountId],
+        write_account_ids: &[AccountId],
+        order: AcquireOrder,
+    ) -> Result<(Vec<ReadAccount>, Vec<WriteAccount<'_>>), LoadError> {
+        if write_account_ids
+            .iter()
+            .any(|account_id| self.pinned.contains(account_id))
+        {
+            return Err(LoadError::ImmutableAccount);
+        }
+        let account_ids: Vec<AccountId> = read_account_ids
+            .iter()
+            .chain(write_account_ids)
+            .copied()
+            .collect();
+        self.initialize_empty_batch(&account_ids)?;
+
+        // (is_write, index into its list, account_id)
+        let mut requests: Vec<(bool, usize, AccountId)> = read_account_ids
+            .iter()
+            .enumerate()
+            .map(|(index, account_id)| (false, index, *account_id))
+            .chain(
+                write_account_ids
+                    .iter()
+                    .enumerate()
+                    .map(|(index, account_id)| (true, index, *account_id)),
+            )
+            .collect();
+        match order {
+            AcquireOrder::AsGiven => {}
+            AcquireOrder::Ascending => requests.sort_by_key(|(_, _, account_id)| *account_id),
+            AcquireOrder::WritesFirst => {
+                requests.sort_by_key(|(is_write, _, account_id)| (!is_write, *account_id))
+            }
+            AcquireOrder::ByShard => requests.sort_by_key(|(_, _, account_id)| {
+                (self.accounts.determine_map(account_id), *account_id)
+            }),
+        }
+
+        let mut read_accounts: Vec<Option<ReadAccount>> =
+            read_account_ids.iter().map(|_| None).collect();
+        let mut write_accounts: Vec<Option<WriteAccount<'_>>> =
+            write_account_ids.iter().map(|_| None).collect();
+        for (is_write, index, account_id) in requests {
+            if is_write {
+                write_accounts[index] = Some(self.write_lock(account_id)?);
+            } else {
+                read_accounts[index] = Some(self.read_lock(account_id)?);
+            }
+        }
+
+        Ok((
+            read_accounts.into_iter().map(Option::unwrap).collect(),
+            write_accounts.into_iter().map(Option::unwrap).collect(),
+        ))
+    }
+
+    fn read_lock(&self, account_id: AccountId) -> Result<ReadAccount, LoadError> {
+        match self.account_lock(account_id)?.try_read_arc() {
+            None => {
+                self.record_contention(account_id);
+                Err(LoadError::OneOrMoreAccountsLocked)
+            }
+            Some(guard) => {
+                self.record_acquisition(account_id);
+                Ok(guard)
+            }
+        }
+    }
+
+    fn write_lock(&self, account_id: AccountId) -> Result<WriteAccount<'_>, LoadError> {
+        match self.account_lock(account_id)?.try_write_arc() {
+            None => {
+                self.record_contention(account_id);
+                Err(LoadError::OneOrMoreAccountsLocked)
+            }
+            Some(guard) => {
+                self.record_acquisition(account_id);
+                Ok(self.write_account(account_id, guard))
+            }
+        }
+    }
+
+    fn record_contention(&self, account_id: AccountId) {
+        let shard = self.accounts.determine_map(&account_id);
+        self.contention[shard].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_acquisition(&self, account_id: AccountId) {
+        let shard = self.accounts.determine_map(&account_id);
+        self.acquisitions[shard].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How many account locks `load_versioned_accounts` has taken so far.
+    pub fn lock_acquisitions(&self) -> u64 {
+        self.acquisitions
+            .iter()
+            .map(|acquisitions| acquisitions.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    pub fn contention_stats(&self) -> ContentionStats {
+        ContentionStats {
+            per_shard: self
+                .contention
+                .iter()
+                .map(|failures| failures.load(Ordering::Relaxed))
+                .collect(),
+        }
+    }
+
+    /// Keep the root where it is until the returned barrier drops: `finalize`,
+    /// `finalize_fast`, `sweep`, `reset_to_genesis` and `restore` wait for it,
+    /// so reads made under the barrier all see the same finalized state.
+    /// Writers on inflight slots carry on as usual. Finalizing on the thread
+    /// holding the barrier deadlocks, and so can taking a second barrier on
+    /// that thread: once a finalizer is waiting, new barriers queue behind it.
+    pub fn read_barrier(&self) -> ReadBarrier<'_> {
+        ReadBarrier {
+            _root: self.root_lock.read().unwrap(),
+        }
+    }
+
+    /// Every account whose lock is held right now, for reading or writing,
+    /// sorted by id. Locks come and go while this runs, so it's only good for
+    /// diagnostics.
+    pub fn currently_locked(&self) -> Vec<AccountId> {
+        let mut account_ids: Vec<AccountId> = self
+            .accounts
+            .iter()
+            .filter(|entry| entry.value().is_locked())
+            .map(|entry| *entry.key())
+            .collect();
+        account_ids.sort_unstable();
+
+        account_ids
+    }
+
+    fn write_account(
+        &self,
+        account_id: AccountId,
+        guard: ArcRwLockWriteGuard<RawRwLock, Arc<VersionedAccount>>,
+    ) -> WriteAccount<'_> {
+        WriteAccount {
+            account_id,
+            guard,
+            accounts_db: self,
+            written_slots: Vec::new(),
+            created_slots: Vec::new(),
+        }
+    }
+
+    /// `VersionedAccount::version` for `account_id`, offset so that it never
+    /// repeats a value handed out before `restore` or `reset_to_genesis` put
+    /// older versions back. `None` if the db has never seen the account.
+    pub fn account_version(&self, account_id: AccountId) -> Option<u64> {
+        let version = self.get_versioned_account(account_id)?.version();
+
+        Some(self.version_floor.load(Ordering::Relaxed) + version)
+    }
+
+    // Move the floor past every version currently handed out, before the
+    // accounts are replaced.
+    fn raise_version_floor(&self) {
+        let highest = self
+            .account_locks()
+            .iter()
+            .map(|(_, account_lock)| account_lock.read().version())
+            .max()
+            .unwrap_or(0);
+        self.version_floor.fetch_add(highest + 1, Ordering::Relaxed);
+    }
+
+    /// Make `account_id` immutable to everything but `set_sysvar`.
+    pub fn pin(&self, account_id: AccountId) {
+        self.pinned.insert(account_id);
+    }
+
+    pub fn is_pinned(&self, account_id: AccountId) -> bool {
+        self.pinned.contains(&account_id)
+    }
+
+    /// Every pinned account id, in no particular order.
+    pub fn pinned(&self) -> Vec<AccountId> {
+        self.pinned.iter().map(|account_id| *account_id).collect()
+    }
+
+    /// The one way to write a pinned account: set it to `account` as of the
+    /// last slot in `ancestors`. Unlike transactions this waits for the
+    /// account's lock rather than failing.
+    pub fn set_sysvar(
+        &self,
+        account_id: AccountId,
+        account: Account,
+        ancestors: &[Slot],
+    ) -> Result<(), LoadError> {
+        let guard = self.account_lock(account_id)?.write_arc();
+        *self
+            .write_account(account_id, guard)
+            .load_account(ancestors)? = account;
+
+        Ok(())
+    }
+
+    /// How many accounts resolve on the fork made of `ancestors`.
+    pub fn account_count(&self, ancestors: &[Slot]) -> u64 {
+        let account_counts = self.account_counts.lock().unwrap();
+
+        account_counts.finalized
+            + ancestors
+                .iter()
+                .filter_map(|slot| account_counts.created.get(slot))
+                .sum::<u64>()
+    }
+
+    /// Root the chain `slots`: `finalize_fast` followed straight away by
+    /// `sweep`.
+    pub fn finalize(&self, slots: &[Slot]) -> FinalizeReport {
+        self.finalize_fast(slots);
+
+        self.sweep()
+    }
+
+    /// `finalize`, but leave the last `lockout` slots of `slots` inflight and
+    /// only root the chain before them. Does nothing if that leaves no slots.
+    pub fn finalize_with_lockout(&self, slots: &[Slot], lockout: u64) -> FinalizeReport {
+        let rooted_len = slots
+            .len()
+            .saturating_sub(usize::try_from(lockout).unwrap_or(usize::MAX));
+        if rooted_len == 0 {
+            return FinalizeReport::default();
+        }
+
+        self.finalize(&slots[..rooted_len])
+    }
+
+    /// The first half of `finalize`: advance the finalized slot and account
+    /// counts to the tip of `slots`, but leave the inflight updates for
+    /// `sweep` to promote or prune. Lookups stay correct in between, since
+    /// updates on `slots` still shadow the old finalized versions and updates
+    /// off it were never visible to forks of the new root.
+    pub fn finalize_fast(&self, slots: &[Slot]) {
+        let _root = self.root_lock.write().unwrap();
+        let tip = *slots.last().unwrap();
+        let finalized_slot = self.finalized_slot.load(Ordering::Relaxed);
+
+        if tip <= finalized_slot {
+            return;
+        }
+
+        let mut account_counts = self.account_counts.lock().unwrap();
+        let inflight = account_counts.created.split_off(&(tip + 1));
+        let rooted = std::mem::replace(&mut account_counts.created, inflight);
+        account_counts.finalized += slots
+            .iter()
+            .filter_map(|slot| rooted.get(slot))
+            .sum::<u64>();
+        drop(account_counts);
+
+        self.finalize_transactions(slots);
+
+        // a later root's chain contains every earlier one, so it replaces any
+        // sweep that's still pending
+        *self.pending_sweep.lock().unwrap() = Some(slots.to_vec());
+        self.finalized_slot.store(tip, Ordering::Relaxed);
+    }
+
+    /// Record that transaction `tx_id` was applied in `slot`, so
+    /// `finality_latency` can report it once that slot is rooted.
+    pub fn record_applied(&self, tx_id: TransactionId, slot: Slot) {
+        self.finality_log
+            .lock()
+            .unwrap()
+            .inflight
+            .entry(tx_id)
+            .or_default()
+            .push(slot);
+    }
+
+    /// The slot `tx_id` was applied in and the root that finalized it, or
+    /// `None` if it hasn't been rooted (or was never recorded). Only the
+    /// latest swept root's transactions are kept.
+    pub fn finality_latency(&self, tx_id: &TransactionId) -> Option<(Slot, Slot)> {
+        self.finality_log
+            .lock()
+            .unwrap()
+            .finalized
+            .get(tx_id)
+            .copied()
+    }
+
+    /// The transactions recorded by `record_applied` in any of `slots`, as
+    /// `(slot, tx_id)` sorted by slot then id.
+    pub fn transactions_applied_in(&self, slots: &[Slot]) -> Vec<(Slot, TransactionId)> {
+        let finality_log = self.finality_log.lock().unwrap();
+        let inflight = finality_log
+            .inflight
+            .iter()
+            .flat_map(|(tx_id, applied_slots)| {
+                applied_slots.iter().map(move |slot| (*slot, *tx_id))
+            });
+        let finalized = finality_log
+            .finalized
+            .iter()
+            .map(|(tx_id, (slot, _))| (*slot, *tx_id));
+
+        let mut applied: Vec<(Slot, TransactionId)> = inflight
+            .chain(finalized)
+            .filter(|(slot, _)| slots.contains(slot))
+            .collect();
+        applied.sort();
+
+        applied
+    }
+
+    // Move transactions applied on the new root's chain to the finalized log
+    // and forget the ones stranded on pruned forks.
+    fn finalize_transactions(&self, slots: &[Slot]) {
+        let tip = *slots.last().unwrap();
+        let mut finality_log = self.finality_log.lock().unwrap();
+        if finality_log.inflight.is_empty() {
+            return;
+        }
+        let chain: HashSet<Slot> = slots.iter().copied().collect();
+
+        let FinalityLog {
+            inflight,
+            finalized,
+        } = &mut *finality_log;
+        inflight.retain(|tx_id, applied_slots| {
+            if let Some(slot) = applied_slots.iter().find(|slot| chain.contains(slot)) {
+                finalized.insert(*tx_id, (*slot, tip));
+                return false;
+            }
+            applied_slots.retain(|slot| *slot > tip);
+
+            !applied_slots.is_empty()
+        });
+    }
+
+    /// `VersionedAccount::compact` for every account along `ancestors`.
+    /// Returns how many updates were dropped.
+    pub fn compact(&self, ancestors: &[Slot]) -> usize {
+        self.account_locks()
+            .iter()
+            .map(|(_, account_lock)| Arc::make_mut(&mut account_lock.write()).compact(ancestors))
+            .sum()
+    }
+
+    /// How many inflight updates are stored across every account and fork.
+    pub fn total_inflight_entries(&self) -> usize {
+        self.account_locks()
+            .iter()
+            .map(|(_, account_lock)| account_lock.read().inflight_updates.len())
+            .sum()
+    }
+
+    /// The second half of `finalize`: promote and prune the inflight updates
+    /// at or below the root set by the last `finalize_fast`. Does nothing if
+    /// there's no sweep pending.
+    pub fn sweep(&self) -> FinalizeReport {
+        let _root = self.root_lock.write().unwrap();
+        let mut report = FinalizeReport::default();
+        let Some(slots) = self.pending_sweep.lock().unwrap().take() else {
+            return report;
+        };
+        let tip = *slots.last().unwrap();
+        // chains can be long, so don't scan them once per update
+        let slots: HashSet<Slot> = slots.iter().copied().collect();
+
+        // each account is only locked while it's rooted
+        let account_locks = self.account_locks();
+
+        account_locks.iter().for_each(|(_, account_lock)| {
+            let mut guard = account_lock.write();
+            let versioned_account = Arc::make_mut(&mut guard);
+            let mut touched = false;
+            while let Some((update_slot, account)) = versioned_account.inflight_updates.pop_front()
+            {
+                if update_slot <= tip {
+                    touched = true;
+                    if slots.contains(&update_slot) {
+                        versioned_account.finalized_acc = Some(account);
+                        report.promoted += 1;
+                    } else {
+                        report.pruned += 1;
+                    }
+                } else {
+                    versioned_account
+                        .inflight_updates
+                        .push_front((update_slot, account));
+                    break;
+                }
+            }
+            report.accounts_touched += touched as usize;
+        });
+
+        self.finality_log
+            .lock()
+            .unwrap()
+            .finalized
+            .retain(|_, (_, root)| *root >= tip);
+
+        report
+    }
+
+    /// What an account that doesn't exist yet starts out as when written.
+    pub fn default_account(&self) -> &Account {
+        &self.config.default_account
+    }
+
+    /// Accounts that resolve on `root_chain` with too little balance to cover
+    /// `rent` for their data, i.e. what collecting rent once that chain is
+    /// rooted would take if nothing else changes. Only a preview: nothing is
+    /// collected. Sorted by id.
+    pub fn rent_preview(&self, root_chain: &[Slot], rent: Rent) -> Vec<AccountId> {
+        let mut account_ids: Vec<AccountId> = self
+            .scan_accounts(root_chain)
+            .into_iter()
+            .filter(|(account_id, account)| {
+                !self.pinned.contains(account_id) && rent.below_minimum(account)
+            })
+            .map(|(account_id, _)| account_id)
+            .collect();
+        account_ids.sort_unstable();
+
+        account_ids
+    }
+
+    /// Compare only rooted state: the finalized slot and each account's
+    /// finalized version. An account that's missing is treated the same as
+    /// one with no finalized version.
+    pub fn finalized_state_eq(&self, other: &AccountsDb) -> bool {
+        let finalized_accounts = |accounts_db: &AccountsDb| -> HashMap<AccountId, Account> {
+            accounts_db
+                .account_locks()
+                .into_iter()
+                .filter_map(|(account_id, account_lock)| {
+                    let account = account_lock.read().finalized_acc.clone()?;
+                    Some((account_id, account))
+                })
+                .collect()
+        };
+
+        self.finalized_slot.load(Ordering::Relaxed) == other.finalized_slot.load(Ordering::Relaxed)
+            && finalized_accounts(self) == finalized_accounts(other)
+    }
+
+    /// Count inflight updates for slots older than the tip of
+    /// `finalized_chain` that aren't on it. Those belong to dead forks and can
+    /// never be rooted.
+    pub fn orphaned_update_count(&self, finalized_chain: &[Slot]) -> usize {
+        let Some(root) = finalized_chain.last() else {
+            return 0;
+        };
+
+        self.account_locks()
+            .iter()
+            .map(|(_, account_lock)| {
+                account_lock
+                    .read()
+                    .inflight_updates
+                    .iter()
+                    .filter(|(slot, _)| slot < root && !finalized_chain.contains(slot))
+                    .count()
+            })
+            .sum()
+    }
+
+    /// Capture every account and the finalized slot. This only bumps
+    /// reference counts; accounts are copied lazily when later written.
+    pub fn snapshot_handle(&self) -> SnapshotHandle {
+        SnapshotHandle {
+            finalized_slot: self.finalized_slot.load(Ordering::Relaxed),
+            account_counts: self.account_counts.lock().unwrap().clone(),
+            accounts: self
+                .account_locks()
+                .into_iter()
+                .map(|(account_id, account_lock)| (account_id, account_lock.read().clone()))
+                .collect(),
+        }
+    }
+
+    /// Replace the db's contents with those captured by `snapshot_handle`.
+    pub fn restore(&self, handle: SnapshotHandle) {
+        let _root = self.root_lock.write().unwrap();
+        self.raise_version_floor();
+        self.accounts.clear();
+        for (account_id, versioned_account) in handle.accounts {
+            self.accounts.insert(
+                account_id,
+                Arc::new(lock_api::RwLock::new(versioned_account)),
+            );
+        }
+        self.finalized_slot
+            .store(handle.finalized_slot, Ordering::Relaxed);
+        *self.account_counts.lock().unwrap() = handle.account_counts;
+    }
+
+    /// Run `VersionedAccount::verify` over every account and check that every
+    /// inflight update is for a slot after the finalized slot.
+    pub fn verify_all(&self) -> Result<(), String> {
+        let finalized_slot = self.finalized_slot.load(Ordering::Relaxed);
+        // until the sweep runs, rooted updates are expected to linger
+        let swept = self.pending_sweep.lock().unwrap().is_none();
+
+        for (account_id, account_lock) in self.account_locks() {
+            let versioned_account = account_lock.read();
+
+            versioned_account
+                .verify()
+                .map_err(|err| format!("account {}: {}", account_id, err))?;
+
+            if let Some((slot, _)) = versioned_account.inflight_updates.front() {
+                if swept && *slot <= finalized_slot {
+                    return Err(format!(
+                        "account {}: inflight update for slot {} is not newer than finalized slot {}",
+                        account_id,
+                        slot,
+                        finalized_slot
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_genesis_database() {
+        let accounts_db = AccountsDb::genesis_database();
+        assert_eq!(accounts_db.len(), 1);
+        assert!(!accounts_db.is_empty());
+        assert!(accounts_db.contains(0));
+        assert!(!accounts_db.contains(1));
+        assert_eq!(
+            accounts_db
+                .get_versioned_account(0)
+                .unwrap()
+                .finalized_acc
+                .as_ref()
+                .unwrap()
+                .balance,
+            GENESIS_SUPPLY
+        );
+    }
+
+    #[test]
+    fn test_initialize_empty_versioned_account() {
+        let accounts_db = AccountsDb::genesis_database();
+        accounts_db.initialize_empty_versioned_account(1).unwrap();
+        assert_eq!(accounts_db.len(), 2);
+        assert!(accounts_db.contains(1));
+    }
+
+    #[test]
+    fn test_load_versioned_accounts() {
+        let accounts_db = AccountsDb::genesis_database();
+        accounts_db.initialize_empty_versioned_account(1).unwrap();
+        accounts_db.initialize_empty_versioned_account(2).unwrap();
+        accounts_db.initialize_empty_versioned_account(3).unwrap();
+
+        {
+            let (read_accounts, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[0, 1], &[2, 3])
+                .expect("load");
+
+            assert_eq!(read_accounts.len(), 2);
+            assert_eq!(write_accounts.len(), 2);
+
+            assert_eq!(
+                read_accounts[0].get_account(&[0]).unwrap().balance,
+                GENESIS_SUPPLY
+            );
+            assert_eq!(write_accounts[0].load_account(&[0]).unwrap().balance, 0);
+
+            let (from_slice, to_slice) = write_accounts.split_at_mut(1);
+
+            let from = &mut from_slice[0];
+            let to = &mut to_slice[0];
+
+            let from = from.load_account(&[0]).unwrap();
+            let to = to.load_account(&[0]).unwrap();
+
+            from.balance = 10;
+            to.balance = 15;
+        }
+
+        assert_eq!(
+            accounts_db
+                .get_versioned_account(3)
+                .unwrap()
+                .get_account(&[0])
+                .unwrap()
+                .balance,
+            15
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let accounts_db = AccountsDb::genesis_database();
+
+        {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[0, 1])
+                .expect("load");
+            write_accounts[0].load_account(&[0, 1]).unwrap().balance -= 5;
+            write_accounts[1].load_account(&[0, 1]).unwrap().balance += 5;
+        }
+
+        assert_eq!(accounts_db.verify_all(), Ok(()));
+
+        let out_of_order = VersionedAccount {
+            finalized_acc: None,
+            inflight_updates: VecDeque::from([
+                (
+                    2,
+                    Account {
+                        balance: 1,
+                        ..Default::default()
+                    },
+                ),
+                (
+                    1,
+                    Account {
+                        balance: 2,
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            version: 0,
+        };
+        assert!(out_of_order.verify().is_err());
+
+        *accounts_db.account_lock(2).unwrap().write() = Arc::new(out_of_order);
+        assert!(accounts_db.verify_all().is_err());
+    }
+
+    #[test]
+    fn test_verify_all_rejects_update_at_root() {
+        let accounts_db = AccountsDb::genesis_database();
+        let write = |slot| {
+            let (_, mut write_accounts) = accounts_db.load_versioned_accounts(&[], &[1]).unwrap();
+            write_accounts[0].set_account(Account::default(), slot);
+        };
+        write(1);
+        accounts_db.finalize(&[0, 1]);
+        assert_eq!(accounts_db.verify_all(), Ok(()));
+
+        // rooting slot 1 already promoted everything written in it
+        write(1);
+        assert!(accounts_db.verify_all().is_err());
+    }
+
+    #[test]
+    fn test_writes_during_finalize() {
+        let accounts_db = Arc::new(AccountsDb::genesis_database());
+
+        for account_id in 1..1_000 {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[account_id])
+                .expect("load");
+            write_accounts[0].load_account(&[0, 1]).unwrap().balance = 1;
+        }
+
+        let finalizer = {
+            let accounts_db = accounts_db.clone();
+            std::thread::spawn(move || accounts_db.finalize(&[0, 1]))
+        };
+
+        for account_id in 1..1_000 {
+            // the finalizer may be holding this account, so spin until it's free
+            let (_, mut write_accounts) = loop {
+                if let Ok(accounts) = accounts_db.load_versioned_accounts(&[], &[account_id]) {
+                    break accounts;
+                }
+            };
+            write_accounts[0].load_account(&[0, 1, 2]).unwrap().balance += 1;
+        }
+
+        finalizer.join().unwrap();
+
+        for account_id in 1..1_000 {
+            let versioned_account = accounts_db.get_versioned_account(account_id).unwrap();
+            assert_eq!(
+                versioned_account.get_account(&[0, 1, 2]).unwrap().balance,
+                2
+            );
+            assert_eq!(versioned_account.get_account(&[0, 1]).unwrap().balance, 1);
+        }
+    }
+
+    #[test]
+    fn test_get_account_with_root() {
+        let accounts_db = AccountsDb::genesis_database();
+        let write = |ancestors: &[Slot]| {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[0])
+                .expect("load");
+            write_accounts[0].load_account(ancestors).unwrap().balance -= 1;
+        };
+
+        write(&[0, 1]);
+        accounts_db.finalize(&[0, 1]);
+
+        // the root bank writing again after being rooted must still see it
+        write(&[0, 1]);
+        let finalized_slot = accounts_db.finalized_slot();
+        assert_eq!(
+            accounts_db
+                .get_versioned_account(0)
+                .unwrap()
+                .get_account_with_root(&[0, 1], finalized_slot)
+                .unwrap()
+                .balance,
+            GENESIS_SUPPLY - 2
+        );
+
+        accounts_db.finalize(&[0, 1, 2]);
+        write(&[0, 1, 2, 3]);
+
+        let versioned_account = accounts_db.get_versioned_account(0).unwrap();
+        let finalized_slot = accounts_db.finalized_slot();
+        for ancestors in [&[0][..], &[0, 1], &[0, 1, 2], &[0, 1, 2, 3]] {
+            assert_eq!(
+                versioned_account.get_account_with_root(ancestors, finalized_slot),
+                versioned_account.get_account(ancestors)
+            );
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn test_benchmark_get_account_with_root() {
+        let ancestors: Vec<Slot> = (0..=1_000).collect();
+        let mut versioned_account = VersionedAccount {
+            finalized_acc: Some(Account::default()),
+            inflight_updates: VecDeque::new(),
+            version: 0,
+        };
+        // descendants of the root have written plenty since
+        for slot in 1_001..2_000 {
+            versioned_account.set_account(Account::default(), slot);
+        }
+
+        let start = std::time::Instant::now();
+        for _ in 0..1_000 {
+            assert!(versioned_account.get_account(&ancestors).is_some());
+        }
+        println!(
+            "get_account elapsed millis: {}",
+            start.elapsed().as_millis()
+        );
+
+        let start = std::time::Instant::now();
+        for _ in 0..1_000 {
+            assert!(versioned_account
+                .get_account_with_root(&ancestors, 1_000)
+                .is_some());
+        }
+        println!(
+            "get_account_with_root elapsed millis: {}",
+            start.elapsed().as_millis()
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn test_benchmark_writes_during_finalize() {
+        let accounts_db = Arc::new(AccountsDb::genesis_database());
+
+        for account_id in 0..100_000 {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[account_id])
+                .expect("load");
+            for slot in 1..=20 {
+                write_accounts[0].load_account(&[slot]).unwrap().balance += 1;
+            }
+        }
+
+        let finalizer = {
+            let accounts_db = accounts_db.clone();
+            std::thread::spawn(move || {
+                let slots: Vec<Slot> = (0..=20).collect();
+                for tip in 1..=20 {
+                    accounts_db.finalize(&slots[..=tip]);
+                }
+            })
+        };
+
+        let start = std::time::Instant::now();
+        let mut writes = 0;
+
+        while !finalizer.is_finished() {
+            if let Ok((_, mut write_accounts)) =
+                accounts_db.load_versioned_accounts(&[], &[writes % 100_000])
+            {
+                write_accounts[0].load_account(&[21]).unwrap().balance += 1;
+                writes += 1;
+            }
+        }
+
+        println!(
+            "{} writes during finalize in {} millis",
+            writes,
+            start.elapsed().as_millis()
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn test_benchmark_finalize_long_chain() {
+        let accounts_db = AccountsDb::genesis_database();
+        // even slots are the rooted chain, odd slots are dead siblings
+        let chain: Vec<Slot> = (0..10_000).map(|index| index * 2).collect();
+
+        for account_id in 0..1_000 {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[account_id])
+                .expect("load");
+            for slot in (account_id % 97..20_000).step_by(997) {
+                write_accounts[0].set_account(Account::default(), slot);
+            }
+        }
+
+        let start = std::time::Instant::now();
+        let report = accounts_db.finalize(&chain);
+        println!(
+            "finalize over a {}-slot chain ({:?}) in {} millis",
+            chain.len(),
+            report,
+            start.elapsed().as_millis()
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn test_benchmark_load_fresh_accounts() {
+        const ROUNDS: usize = 200;
+        let account_ids: Vec<AccountId> = (1..=1_000).collect();
+
+        let dbs: Vec<AccountsDb> = (0..ROUNDS)
+            .map(|_| AccountsDb::genesis_database())
+            .collect();
+        let start = std::time::Instant::now();
+        for accounts_db in &dbs {
+            for account_id in &account_ids {
+                accounts_db
+                    .initialize_empty_versioned_account(*account_id)
+                    .unwrap();
+            }
+        }
+        println!(
+            "initialize {} fresh accounts one at a time x{ROUNDS}: {} millis",
+            account_ids.len(),
+            start.elapsed().as_millis()
+        );
+
+        let dbs: Vec<AccountsDb> = (0..ROUNDS)
+            .map(|_| AccountsDb::genesis_database())
+            .collect();
+        let start = std::time::Instant::now();
+        for accounts_db in &dbs {
+            accounts_db.initialize_empty_batch(&account_ids).unwrap();
+        }
+        println!(
+            "initialize {} fresh accounts in one batch x{ROUNDS}: {} millis",
+            account_ids.len(),
+            start.elapsed().as_millis()
+        );
+
+        let dbs: Vec<AccountsDb> = (0..ROUNDS)
+            .map(|_| AccountsDb::genesis_database())
+            .collect();
+        let start = std::time::Instant::now();
+        for accounts_db in &dbs {
+            drop(
+                accounts_db
+                    .load_versioned_accounts(&[], &account_ids)
+                    .unwrap(),
+            );
+        }
+        println!(
+            "load {} fresh accounts x{ROUNDS}: {} millis",
+            account_ids.len(),
+            start.elapsed().as_millis()
+        );
+
+        let accounts_db = AccountsDb::genesis_database();
+        accounts_db.initialize_empty_batch(&account_ids).unwrap();
+        let start = std::time::Instant::now();
+        for _ in 0..ROUNDS {
+            drop(
+                accounts_db
+                    .load_versioned_accounts(&[], &account_ids)
+                    .unwrap(),
+            );
+        }
+        println!(
+            "load {} existing accounts x{ROUNDS}: {} millis",
+            account_ids.len(),
+            start.elapsed().as_millis()
+        );
+    }
+
+    #[test]
+    fn test_load_account_without_slots() {
+        let mut versioned_account = VersionedAccount::default();
+
+        assert_eq!(
+            versioned_account.load_account(&[]),
+            Err(LoadError::NoSlotsToInclude)
+        );
+        assert!(versioned_account.inflight_updates.is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let accounts_db = AccountsDb::genesis_database();
+        let handle = accounts_db.snapshot_handle();
+
+        {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[0, 1])
+                .expect("load");
+            write_accounts[0].load_account(&[0, 1]).unwrap().balance -= 5;
+            write_accounts[1].load_account(&[0, 1]).unwrap().balance += 5;
+        }
+        accounts_db.finalize(&[0, 1]);
+
+        // the captured accounts weren't touched by the writes above
+        let captured = &handle.accounts[0].1;
+        assert!(captured.inflight_updates.is_empty());
+        assert_eq!(
+            captured.finalized_acc.as_ref().unwrap().balance,
+            GENESIS_SUPPLY
+        );
+
+        accounts_db.restore(handle);
+
+        assert_eq!(accounts_db.len(), 1);
+        assert_eq!(accounts_db.finalized_slot.load(Ordering::Relaxed), 0);
+        assert_eq!(
+            accounts_db
+                .get_versioned_account(0)
+                .unwrap()
+                .get_account(&[0, 1])
+                .unwrap()
+                .balance,
+            GENESIS_SUPPLY
+        );
+    }
+
+    #[test]
+    fn test_max_observed_slot() {
+        let accounts_db = AccountsDb::genesis_database();
+        assert_eq!(accounts_db.max_observed_slot(), 0);
+
+        {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[0, 1])
+                .expect("load");
+            write_accounts[0].load_account(&[0, 3]).unwrap().balance -= 1;
+            write_accounts[1].set_account(
+                Account {
+                    balance: 1,
+                    ..Default::default()
+                },
+                7,
+            );
+        }
+
+        assert_eq!(accounts_db.max_observed_slot(), 7);
+    }
+
+    #[test]
+    fn test_transaction_context_reads_own_writes() {
+        let accounts_db = AccountsDb::genesis_database();
+
+        {
+            let mut ctx = TransactionContext::new(&accounts_db, &[0, 1], &[0, 1]).unwrap();
+
+            ctx.load(0).unwrap().balance -= 10;
+            ctx.load(1).unwrap().balance += 10;
+
+            // loading again mid-transaction neither deadlocks nor loses writes
+            assert_eq!(ctx.load(0).unwrap().balance, GENESIS_SUPPLY - 10);
+            assert_eq!(ctx.load(1).unwrap().balance, 10);
+
+            ctx.load(1).unwrap().balance += 1;
+            assert_eq!(ctx.load(1).unwrap().balance, 11);
+        }
+
+        assert_eq!(
+            accounts_db
+                .get_versioned_account(1)
+                .unwrap()
+                .get_account(&[0, 1])
+                .unwrap()
+                .balance,
+            11
+        );
+    }
+
+    #[test]
+    fn test_transaction_context_creates_accounts_up_front() {
+        let accounts_db = AccountsDb::genesis_database();
+
+        let mut ctx = TransactionContext::new(&accounts_db, &[0, 1], &[1]).unwrap();
+        assert!(accounts_db.contains(1));
+        ctx.load(0).unwrap().balance -= 10;
+        ctx.load(1).unwrap().balance += 10;
+
+        // an account not named up front can't be created once locks are held
+        assert_eq!(ctx.load(2).unwrap_err(), LoadError::OneOrMoreAccountsLocked);
+        assert!(!accounts_db.contains(2));
+    }
+
+    #[test]
+    fn test_orphaned_update_count() {
+        let accounts_db = AccountsDb::genesis_database();
+        let write = |ancestors: &[Slot]| {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[0, 1])
+                .expect("load");
+            write_accounts[0].load_account(ancestors).unwrap().balance -= 1;
+            write_accounts[1].load_account(ancestors).unwrap().balance += 1;
+        };
+
+        write(&[0, 1]);
+        write(&[0, 2]);
+
+        assert_eq!(accounts_db.orphaned_update_count(&[0, 1, 3]), 2);
+
+        accounts_db.finalize(&[0, 1, 3]);
+        assert_eq!(accounts_db.orphaned_update_count(&[0, 1, 3]), 0);
+
+        // slot 2 is dead, but nothing stops a stale bank from writing to it
+        write(&[0, 2]);
+        assert_eq!(accounts_db.orphaned_update_count(&[0, 1, 3]), 2);
+    }
+
+    #[test]
+    fn test_read_many() {
+        let accounts_db = AccountsDb::genesis_database();
+        accounts_db.initialize_empty_versioned_account(1).unwrap();
+
+        assert_eq!(
+            accounts_db.read_many(&[0, 1, 2], &[0]),
+            vec![
+                Some(Account {
+                    balance: GENESIS_SUPPLY,
+                    ..Default::default()
+                }),
+                None,
+                None,
+            ]
+        );
+
+        // nothing is left locked, so writing the same accounts goes through
+        assert!(accounts_db.load_versioned_accounts(&[], &[0, 1, 2]).is_ok());
+    }
+
+    #[test]
+    fn test_fork_updates() {
+        let accounts_db = AccountsDb::genesis_database();
+        let write = |account_id: AccountId, ancestors: &[Slot], balance: u64| {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[account_id])
+                .expect("load");
+            write_accounts[0].load_account(ancestors).unwrap().balance = balance;
+        };
+
+        write(1, &[0, 1], 10);
+        write(2, &[0, 1, 2], 20);
+        write(1, &[0, 3], 30);
+
+        let balances = |ancestors: &[Slot]| -> Vec<(AccountId, Slot, u64)> {
+            accounts_db
+                .fork_updates(ancestors)
+                .into_iter()
+                .map(|(account_id, slot, account)| (account_id, slot, account.balance))
+                .collect()
+        };
+
+        assert_eq!(balances(&[0, 1, 2]), vec![(1, 1, 10), (2, 2, 20)]);
+        assert_eq!(balances(&[0, 3]), vec![(1, 3, 30)]);
+        assert_eq!(balances(&[0]), vec![]);
+    }
+
+    #[test]
+    fn test_max_accounts() {
+        let accounts_db = AccountsDb::genesis_database_with_config(AccountsDbConfig {
+            max_accounts: Some(3),
+            ..Default::default()
+        });
+
+        accounts_db.initialize_empty_versioned_account(1).unwrap();
+        assert!(accounts_db.load_versioned_accounts(&[], &[0, 2]).is_ok());
+
+        assert_eq!(
+            accounts_db.initialize_empty_versioned_account(3),
+            Err(LoadError::AccountLimitReached)
+        );
+        assert_eq!(
+            accounts_db.load_versioned_accounts(&[3], &[]).err(),
+            Some(LoadError::AccountLimitReached)
+        );
+        // existing accounts are still usable
+        assert!(accounts_db.load_versioned_accounts(&[], &[1, 2]).is_ok());
+        assert_eq!(accounts_db.len(), 3);
+    }
+
+    #[test]
+    fn test_finalize_report() {
+        let accounts_db = AccountsDb::genesis_database();
+
+        // fork 0 -> 1 -> 3 touches accounts 0 and 1, fork 0 -> 2 touches 0 and
+        // 2, and slot 4 is above the root so stays inflight
+        for (slot, account_ids) in [(1, vec![0, 1]), (2, vec![0, 2]), (3, vec![1]), (4, vec![0])] {
+            let (_, mut write) = accounts_db
+                .load_versioned_accounts(&[], &account_ids)
+                .unwrap();
+            for account in write.iter_mut() {
+                account.load_account(&[slot]).unwrap().balance += 1;
+            }
+        }
+
+        assert_eq!(
+            accounts_db.finalize(&[0, 1, 3]),
+            FinalizeReport {
+                promoted: 3,
+                pruned: 2,
+                accounts_touched: 3,
+            }
+        );
+        // nothing left at or below the root
+        assert_eq!(accounts_db.finalize(&[0, 1, 3]), FinalizeReport::default());
+    }
+
+    #[test]
+    fn test_contention_stats() {
+        let accounts_db = AccountsDb::genesis_database();
+        assert_eq!(accounts_db.contention_stats().total(), 0);
+
+        let (_, _write_accounts) = accounts_db.load_versioned_accounts(&[], &[0]).unwrap();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..3 {
+                    assert!(accounts_db.load_versioned_accounts(&[0], &[]).is_err());
+                }
+                assert!(accounts_db.load_versioned_accounts(&[], &[0]).is_err());
+            });
+        });
+
+        let stats = accounts_db.contention_stats();
+        assert_eq!(stats.total(), 4);
+        assert_eq!(stats.per_shard[accounts_db.accounts.determine_map(&0)], 4);
+        // only the lock that was actually taken counts as acquired
+        assert_eq!(accounts_db.lock_acquisitions(), 1);
+    }
+
+    #[test]
+    fn test_scan_does_not_hold_shard_while_waiting() {
+        let accounts_db = AccountsDb::genesis_database_with_config(AccountsDbConfig {
+            shard_amount: Some(2),
+            ..Default::default()
+        });
+        let shard = accounts_db.accounts.determine_map(&0);
+        let new_id = (1..)
+            .find(|account_id| accounts_db.accounts.determine_map(account_id) == shard)
+            .unwrap();
+
+        let (_, write_accounts) = accounts_db.load_versioned_accounts(&[], &[0]).unwrap();
+        std::thread::scope(|scope| {
+            let scan = scope.spawn(|| accounts_db.scan_accounts(&[0]).len());
+            // give the scan time to block on account 0
+            std::thread::sleep(std::time::Duration::from_millis(50));
+
+            // creating an account in the same shard doesn't wait on the scan
+            accounts_db.initialize_empty_batch(&[new_id]).unwrap();
+            drop(write_accounts);
+            assert_eq!(scan.join().unwrap(), 1);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "shard_amount must be a power of two greater than 1, got 3")]
+    fn test_invalid_shard_amount() {
+        AccountsDb::from_state_with_config(
+            BTreeMap::new(),
+            AccountsDbConfig {
+                shard_amount: Some(3),
+                ..Default::default()
+            },
+        );
+    }
+
+    #[test]
+    fn test_shard_amount() {
+        let accounts_db = AccountsDb::genesis_database_with_config(AccountsDbConfig {
+            shard_amount: Some(64),
+            ..Default::default()
+        });
+        assert_eq!(accounts_db.accounts.shards().len(), 64);
+        assert_eq!(accounts_db.contention_stats().per_shard.len(), 64);
+
+        {
+            let (_, mut write_accounts) =
+                accounts_db.load_versioned_accounts(&[], &[0, 1]).unwrap();
+            write_accounts[0].load_account(&[0, 1]).unwrap().balance -= 5;
+            write_accounts[1].load_account(&[0, 1]).unwrap().balance += 5;
+        }
+        accounts_db.finalize(&[0, 1]);
+
+        assert_eq!(accounts_db.len(), 2);
+        assert_eq!(
+            accounts_db.read_many(&[0, 1], &[0, 1]),
+            vec![
+                Some(Account {
+                    balance: GENESIS_SUPPLY - 5,
+                    ..Default::default()
+                }),
+                Some(Account {
+                    balance: 5,
+                    ..Default::default()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_default_account() {
+        let stipend = Account {
+            balance: 100,
+            ..Default::default()
+        };
+        let accounts_db = AccountsDb::genesis_database_with_config(AccountsDbConfig {
+            default_account: stipend.clone(),
+            ..Default::default()
+        });
+
+        {
+            let (_, mut write_accounts) =
+                accounts_db.load_versioned_accounts(&[], &[0, 1]).unwrap();
+            // existing accounts keep their balance
+            assert_eq!(
+                write_accounts[0].load_account(&[0, 1]).unwrap().balance,
+                GENESIS_SUPPLY
+            );
+            assert_eq!(*write_accounts[1].load_account(&[0, 1]).unwrap(), stipend);
+            write_accounts[1].load_account(&[0, 1]).unwrap().balance += 5;
+        }
+
+        assert_eq!(
+            accounts_db.read_many(&[1, 2], &[0, 1]),
+            vec![
+                Some(Account {
+                    balance: 105,
+                    ..Default::default()
+                }),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_preallocate() {
+        let accounts_db = AccountsDb::genesis_database();
+
+        accounts_db.preallocate(0..10_000).unwrap();
+
+        assert_eq!(accounts_db.len(), 10_000);
+        assert!((0..10_000).all(|account_id| accounts_db.contains(account_id)));
+        // nothing was written, so only genesis resolves
+        assert_eq!(accounts_db.account_count(&[0]), 1);
+        assert_eq!(accounts_db.read_many(&[0, 1], &[0])[1], None);
+        assert_eq!(accounts_db.max_observed_slot(), 0);
+
+        let capped = AccountsDb::genesis_database_with_config(AccountsDbConfig {
+            max_accounts: Some(5),
+            ..Default::default()
+        });
+        assert_eq!(
+            capped.preallocate(0..10),
+            Err(LoadError::AccountLimitReached)
+        );
+        assert_eq!(capped.len(), 5);
+    }
+
+    #[test]
+    fn test_from_state() {
+        let state = BTreeMap::from([
+            (
+                1,
+                Account {
+                    balance: 10,
+                    ..Default::default()
+                },
+            ),
+            (
+                5,
+                Account {
+                    balance: 50,
+                    owner: 1,
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let accounts_db = AccountsDb::from_state(state.clone());
+
+        assert_eq!(accounts_db.finalized_slot(), 0);
+        assert_eq!(accounts_db.account_count(&[0]), 2);
+        assert_eq!(
+            accounts_db.read_many(&[0, 1, 5], &[0]),
+            vec![None, state.get(&1).cloned(), state.get(&5).cloned()]
+        );
+        assert_eq!(accounts_db.verify_all(), Ok(()));
+
+        // imported accounts are rooted, so every fork sees them
+        {
+            let (_, mut write_accounts) = accounts_db.load_versioned_accounts(&[], &[1]).unwrap();
+            write_accounts[0].load_account(&[0, 1]).unwrap().balance += 1;
+        }
+        assert_eq!(
+            accounts_db.read_many(&[1], &[0, 2]),
+            vec![state.get(&1).cloned()]
+        );
+    }
+
+    #[test]
+    fn test_finalize_fast_then_sweep() {
+        let accounts_db = AccountsDb::genesis_database();
+        let write = |slots: &[Slot], account_id, balance| {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[account_id])
+                .unwrap();
+            write_accounts[0].load_account(slots).unwrap().balance = balance;
+        };
+        let balance = |ancestors: &[Slot], account_id| {
+            accounts_db
+                .get_versioned_account(account_id)
+                .unwrap()
+                .get_account_with_root(ancestors, accounts_db.finalized_slot())
+                .map(|account| account.balance)
+        };
+
+        // 0 -> 1 -> 3 is rooted, 2 is a dead sibling, 4 builds on the root
+        write(&[0, 1], 1, 10);
+        write(&[0, 2], 1, 20);
+        write(&[0, 2], 2, 20);
+        write(&[0, 1, 3], 1, 30);
+        write(&[0, 1, 3, 4], 2, 40);
+
+        accounts_db.finalize_fast(&[0, 1, 3]);
+        assert_eq!(accounts_db.finalized_slot(), 3);
+        assert_eq!(accounts_db.account_count(&[0, 1, 3]), 2);
+
+        assert_eq!(balance(&[0, 1], 1), Some(10));
+        assert_eq!(balance(&[0, 1, 3], 1), Some(30));
+        assert_eq!(balance(&[0, 1, 3], 2), None);
+        assert_eq!(balance(&[0, 1, 3, 4], 1), Some(30));
+        assert_eq!(balance(&[0, 1, 3, 4], 2), Some(40));
+        assert_eq!(accounts_db.verify_all(), Ok(()));
+
+        assert_eq!(
+            accounts_db.sweep(),
+            FinalizeReport {
+                promoted: 2,
+                pruned: 2,
+                accounts_touched: 2,
+            }
+        );
+        // a second sweep has nothing to do, and reads are unchanged
+        // apart from history below the root being gone
+        assert_eq!(accounts_db.sweep(), FinalizeReport::default());
+        assert_eq!(balance(&[0, 1, 3], 1), Some(30));
+        assert_eq!(balance(&[0, 1, 3, 4], 2), Some(40));
+        assert_eq!(accounts_db.verify_all(), Ok(()));
+    }
+
+    #[test]
+    fn test_accounts_modified_in() {
+        let accounts_db = AccountsDb::genesis_database();
+        for (slots, account_ids) in [
+            (&[0, 1][..], &[0, 1][..]),
+            (&[0, 1, 2], &[1, 3, 4]),
+            (&[0, 2], &[5]),
+        ] {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], account_ids)
+                .unwrap();
+            for write_account in write_accounts.iter_mut() {
+                write_account.load_account(slots).unwrap().balance += 1;
+            }
+        }
+
+        assert_eq!(accounts_db.accounts_modified_in(2), vec![1, 3, 4, 5]);
+        assert_eq!(accounts_db.accounts_modified_in(1), vec![0, 1]);
+        assert_eq!(accounts_db.accounts_modified_in(3), vec![]);
+    }
+
+    #[test]
+    fn test_acquire_order() {
+        let accounts_db = AccountsDb::genesis_database();
+
+        for order in [
+            AcquireOrder::Ascending,
+            AcquireOrder::AsGiven,
+            AcquireOrder::WritesFirst,
+            AcquireOrder::ByShard,
+        ] {
+            let (read_accounts, mut write_accounts) = accounts_db
+                .load_versioned_accounts_with_order(&[9, 0], &[5, 1, 3], order)
+                .unwrap();
+
+            assert_eq!(
+                read_accounts[1].get_account(&[0]).unwrap().balance,
+                GENESIS_SUPPLY
+            );
+            assert!(read_accounts[0].get_account(&[0]).is_none());
+            // accounts come back in the order they were asked for
+            let write_ids: Vec<AccountId> = write_accounts
+                .iter_mut()
+                .map(|write_account| {
+                    write_account.load_account(&[0, 1]).unwrap().balance += 1;
+                    write_account.account_id
+                })
+                .collect();
+            assert_eq!(write_ids, vec![5, 1, 3]);
+        }
+
+        // every strategy's writes landed
+        assert!(accounts_db
+            .read_many(&[1, 3, 5], &[0, 1])
+            .iter()
+            .all(|account| account.as_ref().unwrap().balance == 4));
+    }
+
+    #[test]
+    fn test_inflight_value() {
+        let accounts_db = AccountsDb::genesis_database();
+        assert_eq!(accounts_db.inflight_value(), 0);
+
+        {
+            let (_, mut write_accounts) =
+                accounts_db.load_versioned_accounts(&[], &[0, 1]).unwrap();
+            write_accounts[0].load_account(&[0, 1]).unwrap().balance -= 10;
+            write_accounts[1].load_account(&[0, 1]).unwrap().balance += 10;
+        }
+        assert_eq!(accounts_db.inflight_value(), GENESIS_SUPPLY);
+
+        // a later write on another fork replaces account 1's contribution
+        {
+            let (_, mut write_accounts) = accounts_db.load_versioned_accounts(&[], &[1]).unwrap();
+            write_accounts[0].load_account(&[0, 2]).unwrap().balance += 3;
+        }
+        assert_eq!(accounts_db.inflight_value(), GENESIS_SUPPLY - 10 + 3);
+
+        accounts_db.finalize(&[0, 2]);
+        assert_eq!(accounts_db.inflight_value(), 0);
+    }
+
+    #[test]
+    fn test_get_account_ex() {
+        let mut versioned_account = VersionedAccount {
+            finalized_acc: Some(Account {
+                balance: 1,
+                ..Default::default()
+            }),
+            inflight_updates: VecDeque::new(),
+            version: 0,
+        };
+        versioned_account.set_account(
+            Account {
+                balance: 2,
+                ..Default::default()
+            },
+            2,
+        );
+
+        // changed on this fork
+        assert_eq!(
+            versioned_account
+                .get_account_ex(&[0, 2], false)
+                .unwrap()
+                .balance,
+            2
+        );
+        assert_eq!(
+            versioned_account
+                .get_account_ex(&[0, 2], true)
+                .unwrap()
+                .balance,
+            2
+        );
+        // unchanged since finalization
+        assert_eq!(versioned_account.get_account_ex(&[0, 1], false), None);
+        assert_eq!(
+            versioned_account
+                .get_account_ex(&[0, 1], true)
+                .unwrap()
+                .balance,
+            1
+        );
+    }
+
+    #[test]
+    fn test_rent_preview() {
+        let accounts_db = AccountsDb::genesis_database();
+        let rent = Rent { per_byte: 10 };
+        {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[0, 1, 2, 3])
+                .unwrap();
+            write_accounts[0].load_account(&[0, 1]).unwrap().balance -= 120;
+            // dust: 4 bytes of data need 40
+            let dust = write_accounts[1].load_account(&[0, 1]).unwrap();
+            dust.balance = 20;
+            dust.data = vec![0; 4];
+            let covered = write_accounts[2].load_account(&[0, 1]).unwrap();
+            covered.balance = 100;
+            covered.data = vec![0; 4];
+            // dust, but only on a fork that isn't rooted
+            let other_fork = write_accounts[3].load_account(&[0, 2]).unwrap();
+            other_fork.data = vec![0; 1];
+        }
+
+        assert_eq!(accounts_db.rent_preview(&[0, 1], rent), vec![1]);
+        assert_eq!(accounts_db.rent_preview(&[0, 2], rent), vec![3]);
+        assert_eq!(accounts_db.rent_preview(&[0, 1], Rent::default()), vec![]);
+
+        // finalizing doesn't collect anything
+        accounts_db.finalize(&[0, 1]);
+        assert_eq!(
+            accounts_db.read_many(&[1], &[0, 1])[0]
+                .as_ref()
+                .unwrap()
+                .balance,
+            20
+        );
+        assert_eq!(accounts_db.account_count(&[0, 1]), 3);
+        assert_eq!(accounts_db.rent_preview(&[0, 1], rent), vec![1]);
+    }
+
+    #[test]
+    fn test_lock_guard() {
+        let accounts_db = AccountsDb::genesis_database();
+        let ancestors = [0, 1];
+        {
+            let mut guard = accounts_db
+                .lock_accounts(&ancestors, &[2], &[0, 1])
+                .unwrap();
+            guard.write(0).unwrap().balance -= 30;
+            guard.write(1).unwrap().balance += 30;
+
+            assert_eq!(guard.read(1).unwrap().balance, 30);
+            assert_eq!(guard.read(2), None);
+            assert_eq!(guard.write(2), Err(LoadError::AccountNotLocked(2)));
+            assert_eq!(guard.write(3), Err(LoadError::AccountNotLocked(3)));
+            assert!(matches!(
+                accounts_db.lock_accounts(&ancestors, &[], &[1]),
+                Err(LoadError::OneOrMoreAccountsLocked)
+            ));
+        }
+
+        let accounts = accounts_db.read_many(&[0, 1], &ancestors);
+        assert_eq!(accounts[0].as_ref().unwrap().balance, GENESIS_SUPPLY - 30);
+        assert_eq!(accounts[1].as_ref().unwrap().balance, 30);
+        assert!(accounts_db.lock_accounts(&ancestors, &[], &[1]).is_ok());
+    }
+
+    #[test]
+    fn test_reset_to_genesis() {
+        let accounts_db = AccountsDb::genesis_database();
+        for slot in 1..=3 {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[0, slot])
+                .unwrap();
+            write_accounts[0].load_account(&[0, slot]).unwrap().balance -= slot;
+            write_accounts[1].load_account(&[0, slot]).unwrap().balance += slot;
+        }
+        accounts_db.finalize(&[0, 2]);
+        assert_eq!(accounts_db.account_count(&[0, 2, 3]), 3);
+
+        accounts_db.reset_to_genesis();
+
+        for ancestors in [&[0][..], &[0, 2], &[0, 3]] {
+            assert_eq!(
+                accounts_db.read_many(&[0, 1, 2, 3], ancestors),
+                vec![Some(genesis_account()), None, None, None]
+            );
+            assert_eq!(accounts_db.account_count(ancestors), 1);
+        }
+        assert_eq!(accounts_db.finalized_slot(), 0);
+        assert_eq!(accounts_db.max_observed_slot(), 0);
+        assert_eq!(accounts_db.verify_all(), Ok(()));
+        assert_eq!(accounts_db.accounts_modified_in_any(&[1, 2, 3]), vec![]);
+    }
+
+    #[test]
+    fn test_account_version() {
+        let accounts_db = AccountsDb::genesis_database();
+        assert_eq!(accounts_db.account_version(0), Some(0));
+        assert_eq!(accounts_db.account_version(1), None);
+
+        let mut versions = vec![];
+        for slot in [1, 2, 2] {
+            let (_, mut write_accounts) = accounts_db.load_versioned_accounts(&[], &[1]).unwrap();
+            write_accounts[0].set_account(
+                Account {
+                    balance: slot,
+                    ..Default::default()
+                },
+                slot,
+            );
+            drop(write_accounts);
+            versions.push(accounts_db.account_version(1).unwrap());
+        }
+        assert_eq!(versions, vec![1, 2, 3]);
+
+        // reads leave it alone
+        accounts_db.read_many(&[1], &[0, 2]);
+        assert_eq!(accounts_db.account_version(1), Some(3));
+        assert_eq!(accounts_db.account_version(0), Some(0));
+
+        // putting older versions back doesn't hand out old values again
+        let handle = accounts_db.snapshot_handle();
+        let (_, mut write_accounts) = accounts_db.load_versioned_accounts(&[], &[1]).unwrap();
+        write_accounts[0].load_account(&[0, 3]).unwrap().balance += 1;
+        drop(write_accounts);
+        let after_write = accounts_db.account_version(1).unwrap();
+        accounts_db.restore(handle);
+        assert!(accounts_db.account_version(1).unwrap() > after_write);
+        assert!(accounts_db.account_version(0).unwrap() > after_write);
+
+        let before_reset = accounts_db.account_version(0).unwrap();
+        accounts_db.reset_to_genesis();
+        assert!(accounts_db.account_version(0).unwrap() > before_reset);
+    }
+
+    #[test]
+    fn test_finalized_sorted() {
+        let state: BTreeMap<AccountId, Account> = [9, 3, 700, 1, 42]
+            .into_iter()
+            .map(|account_id| {
+                let account = Account {
+                    balance: account_id,
+                    ..Default::default()
+                };
+                (account_id, account)
+            })
+            .collect();
+        let accounts_db = AccountsDb::from_state(state.clone());
+        // inflight only, so not finalized
+        let (_, mut write_accounts) = accounts_db.load_versioned_accounts(&[], &[5, 9]).unwrap();
+        write_accounts[0].load_account(&[0, 1]).unwrap().balance = 5;
+        write_accounts[1].load_account(&[0, 1]).unwrap().balance = 0;
+        drop(write_accounts);
+
+        assert_eq!(
+            accounts_db.finalized_sorted().collect::<Vec<_>>(),
+            state.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_currently_locked() {
+        let accounts_db = Arc::new(AccountsDb::genesis_database());
+        accounts_db.preallocate(1..4).unwrap();
+        assert_eq!(accounts_db.currently_locked(), vec![]);
+
+        let (locked_sender, locked) = std::sync::mpsc::channel();
+        let (release, release_receiver) = std::sync::mpsc::channel::<()>();
+        let holder = {
+            let accounts_db = accounts_db.clone();
+            std::thread::spawn(move || {
+                let _accounts = accounts_db.load_versioned_accounts(&[1], &[3]).unwrap();
+                locked_sender.send(()).unwrap();
+                release_receiver.recv().unwrap();
+            })
+        };
+
+        locked.recv().unwrap();
+        assert_eq!(accounts_db.currently_locked(), vec![1, 3]);
+
+        release.send(()).unwrap();
+        holder.join().unwrap();
+        assert_eq!(accounts_db.currently_locked(), vec![]);
+    }
+
+    #[test]
+    fn test_read_barrier() {
+        let accounts_db = Arc::new(AccountsDb::genesis_database());
+        for account_id in 1..100 {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[0, account_id])
+                .unwrap();
+            write_accounts[0].load_account(&[0, 1]).unwrap().balance -= 1;
+            write_accounts[1].load_account(&[0, 1]).unwrap().balance += 1;
+        }
+        let finalized_state = |accounts_db: &AccountsDb| {
+            let mut accounts = accounts_db.scan_accounts(&[]);
+            accounts.sort_unstable_by_key(|(account_id, _)| *account_id);
+            accounts
+        };
+
+        let barrier = accounts_db.read_barrier();
+        let before = finalized_state(&accounts_db);
+        let finalizer = {
+            let accounts_db = accounts_db.clone();
+            std::thread::spawn(move || accounts_db.finalize(&[0, 1]))
+        };
+        for _ in 0..10 {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            assert_eq!(accounts_db.finalized_slot(), 0);
+            assert_eq!(finalized_state(&accounts_db), before);
+        }
+        drop(barrier);
+
+        assert_eq!(finalizer.join().unwrap().promoted, 100);
+        assert_eq!(accounts_db.finalized_slot(), 1);
+        assert_eq!(finalized_state(&accounts_db).len(), 100);
+
+        // restoring waits for the barrier too
+        let handle = accounts_db.snapshot_handle();
+        accounts_db.reset_to_genesis();
+        let barrier = accounts_db.read_barrier();
+        let restorer = {
+            let accounts_db = accounts_db.clone();
+            std::thread::spawn(move || accounts_db.restore(handle))
+        };
+        for _ in 0..10 {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            assert_eq!(finalized_state(&accounts_db).len(), 1);
+        }
+        drop(barrier);
+
+        restorer.join().unwrap();
+        assert_eq!(accounts_db.finalized_slot(), 1);
+        assert_eq!(finalized_state(&accounts_db).len(), 100);
+    }
+
+    #[test]
+    fn test_compact() {
+        let accounts_db = AccountsDb::genesis_database();
+        let chain: Vec<Slot> = (0..=6).collect();
+        // the balance only changes at slots 2 and 5; 3 starts a side fork
+        for slot in 1..=6 {
+            let (_, mut write_accounts) = accounts_db.load_versioned_accounts(&[], &[0]).unwrap();
+            let account = write_accounts[0]
+                .load_account(&chain[..=slot as usize])
+                .unwrap();
+            if slot == 2 || slot == 5 {
+                account.balance -= slot;
+            }
+        }
+        let (_, mut write_accounts) = accounts_db.load_versioned_accounts(&[], &[0]).unwrap();
+        write_accounts[0].load_account(&[0, 1, 2, 3, 10]).unwrap();
+        drop(write_accounts);
+
+        let forks = [
+            &chain[..],
+            &[0, 1],
+            &[0, 1, 2, 3],
+            &[0, 1, 2, 3, 4],
+            &[0, 1, 2, 3, 10],
+        ];
+        let balances = |accounts_db: &AccountsDb| {
+            forks
+                .iter()
+                .map(|fork| {
+                    accounts_db.read_many(&[0], fork)[0]
+                        .as_ref()
+                        .unwrap()
+                        .balance
+                })
+                .collect::<Vec<_>>()
+        };
+        let before = balances(&accounts_db);
+        assert_eq!(accounts_db.total_inflight_entries(), 7);
+
+        assert_eq!(accounts_db.compact(&chain), 4);
+
+        assert_eq!(accounts_db.total_inflight_entries(), 3);
+        assert_eq!(balances(&accounts_db), before);
+        assert_eq!(accounts_db.compact(&chain), 0);
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut versioned_account = VersionedAccount::default();
+        assert_eq!(
+            versioned_account.explain(&[0]),
+            ResolutionTrace {
+                resolution: Resolution::Missing,
+                skipped: vec![],
+            }
+        );
+
+        versioned_account.finalized_acc = Some(Account::default());
+        for slot in [1, 2, 3, 5] {
+            versioned_account.set_account(Account::default(), slot);
+        }
+
+        assert_eq!(
+            versioned_account.explain(&[0, 1, 2, 4]),
+            ResolutionTrace {
+                resolution: Resolution::Inflight(2),
+                skipped: vec![5, 3],
+            }
+        );
+        assert_eq!(
+            versioned_account.explain(&[0, 4]),
+            ResolutionTrace {
+                resolution: Resolution::Finalized,
+                skipped: vec![5, 3, 2, 1],
+            }
+        );
+        assert_eq!(
+            versioned_account.explain(&[0, 1, 2, 3, 5]).resolution,
+            Resolution::Inflight(5)
+        );
+    }
+
+    #[test]
+    fn test_initialize_empty_batch() {
+        let accounts_db = AccountsDb::genesis_database_with_config(AccountsDbConfig {
+            max_accounts: Some(6),
+            ..Default::default()
+        });
+
+        accounts_db
+            .initialize_empty_batch(&[3, 1, 0, 3, 2])
+            .unwrap();
+        assert_eq!(accounts_db.accounts.len(), 4);
+        assert_eq!(
+            accounts_db.read_many(&[0], &[0])[0],
+            Some(genesis_account())
+        );
+
+        // 4, 5 and 6 don't all fit, so none go in
+        assert_eq!(
+            accounts_db.initialize_empty_batch(&[4, 5, 6, 1]),
+            Err(LoadError::AccountLimitReached)
+        );
+        assert_eq!(accounts_db.accounts.len(), 4);
+        accounts_db.initialize_empty_batch(&[4, 5, 1]).unwrap();
+        assert_eq!(accounts_db.accounts.len(), 6);
+    }
+
+    #[test]
+    fn test_finalized_account() {
+        let accounts_db = AccountsDb::genesis_database();
+        let (_, mut write_accounts) = accounts_db.load_versioned_accounts(&[], &[0, 1]).unwrap();
+        write_accounts[0].load_account(&[0, 1]).unwrap().balance -= 5;
+        write_accounts[1].load_account(&[0, 1]).unwrap().balance += 5;
+        drop(write_accounts);
+
+        assert_eq!(accounts_db.finalized_account(0), Some(genesis_account()));
+        assert_eq!(accounts_db.finalized_account(1), None);
+        assert_eq!(accounts_db.finalized_account(2), None);
+
+        accounts_db.finalize(&[0, 1]);
+        assert_eq!(accounts_db.finalized_account(1).unwrap().balance, 5);
+    }
+
+    #[test]
+    fn test_peek_latest() {
+        let mut versioned_account = VersionedAccount {
+            finalized_acc: Some(Account::default()),
+            ..Default::default()
+        };
+        assert_eq!(versioned_account.peek_latest(), None);
+
+        for (slot, balance) in [(4, 40), (1, 10), (7, 70), (2, 20)] {
+            versioned_account.set_account(
+                Account {
+                    balance,
+                    ..Default::default()
+                },
+                slot,
+            );
+        }
+
+        let (slot, account) = versioned_account.peek_latest().unwrap();
+        assert_eq!((slot, account.balance), (7, 70));
+        assert_eq!(
+            versioned_account
+                .inflight_updates
+                .back()
+                .map(|(slot, _)| *slot),
+            Some(slot)
+        );
+    }
+
+    #[test]
+    fn test_from_genesis_config() {
+        let allocation = |account_id, balance| {
+            let account = Account {
+                balance,
+                ..Default::default()
+            };
+            (account_id, account)
+        };
+        let genesis = |allocations| GenesisConfig {
+            supply: 100,
+            allocations,
+        };
+
+        assert!(matches!(
+            AccountsDb::from_genesis_config(
+                genesis(vec![allocation(1, 60), allocation(2, 39)]),
+                AccountsDbConfig::default()
+            ),
+            Err(GenesisError::SupplyMismatch {
+                supply: 100,
+                allocated: 99
+            })
+        ));
+        assert!(matches!(
+            AccountsDb::from_genesis_config(
+                genesis(vec![allocation(1, 60), allocation(1, 40)]),
+                AccountsDbConfig::default()
+            ),
+            Err(GenesisError::DuplicateAccount(1))
+        ));
+        for shard_amount in [0, 1, 3] {
+            assert!(matches!(
+                AccountsDb::from_genesis_config(
+                    genesis(vec![allocation(1, 100)]),
+                    AccountsDbConfig {
+                        shard_amount: Some(shard_amount),
+                        ..Default::default()
+                    }
+                ),
+                Err(GenesisError::InvalidShardAmount(invalid)) if invalid == shard_amount
+            ));
+        }
+
+        let accounts_db = AccountsDb::from_genesis_config(
+            genesis(vec![allocation(2, 40), allocation(1, 60)]),
+            AccountsDbConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(accounts_db.account_count(&[0]), 2);
+        assert_eq!(
+            accounts_db.finalized_sorted().collect::<Vec<_>>(),
+            vec![allocation(1, 60), allocation(2, 40)]
+        );
+    }
+
+    #[test]
+    fn test_set_account_counts_new_accounts() {
+        let accounts_db = AccountsDb::genesis_database();
+        let set = |account_id, slot| {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[account_id])
+                .unwrap();
+            write_accounts[0].set_account(Account::default(), slot);
+        };
+
+        set(1, 1);
+        // neither an existing account nor a second write counts again
+        set(0, 1);
+        set(1, 2);
+        assert_eq!(accounts_db.account_count(&[0, 1]), 2);
+        assert_eq!(accounts_db.account_count(&[0, 1, 2]), 2);
     }
 }
@@ -0,0 +1,217 @@
+//! Per-account update subscriptions. Each write made through a `WriteAccount`
+//! is delivered to the account's subscribers as `(slot, account)` when the
+//! `WriteAccount` is dropped, just before its lock is released, into a bounded
+//! buffer per subscription. What happens when a buffer is full is up to its
+//! `OverflowPolicy`.
+
+use super::*;
+
+use std::sync::atomic::AtomicBool;
+use std::sync::Condvar;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowPolicy {
+    /// Make room by discarding the oldest buffered update.
+    DropOldest,
+    /// Discard the incoming update.
+    DropNewest,
+    /// Make the writer wait until the subscriber catches up or drops the
+    /// subscription. The writer still holds the account's lock while it
+    /// waits, so the subscriber must not be the one writing.
+    Block,
+}
+
+pub(super) struct SubscriptionBuffer {
+    capacity: usize,
+    policy: OverflowPolicy,
+    updates: Mutex<VecDeque<(Slot, Account)>>,
+    not_full: Condvar,
+    // set, under `updates`' lock, once the subscription is dropped
+    closed: AtomicBool,
+}
+
+impl SubscriptionBuffer {
+    fn push(&self, update: (Slot, Account)) {
+        let mut updates = self.updates.lock().unwrap();
+        let closed = || self.closed.load(Ordering::Relaxed);
+
+        if updates.len() >= self.capacity && !closed() {
+            match self.policy {
+                OverflowPolicy::DropOldest => {
+                    updates.pop_front();
+                }
+                OverflowPolicy::DropNewest => return,
+                OverflowPolicy::Block => {
+                    updates = self
+                        .not_full
+                        .wait_while(updates, |updates| {
+                            updates.len() >= self.capacity && !closed()
+                        })
+                        .unwrap();
+                }
+            }
+        }
+
+        // nobody is left to read it
+        if closed() {
+            return;
+        }
+        updates.push_back(update);
+    }
+}
+
+/// Receives updates to one account until dropped.
+pub struct Subscription {
+    buffer: Arc<SubscriptionBuffer>,
+}
+
+impl Subscription {
+    /// The oldest buffered update, if any.
+    pub fn try_recv(&self) -> Option<(Slot, Account)> {
+        let update = self.buffer.updates.lock().unwrap().pop_front();
+        self.buffer.not_full.notify_all();
+
+        update
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // a writer blocked on this buffer would otherwise wait forever
+        let _updates = self.buffer.updates.lock().unwrap();
+        self.buffer.closed.store(true, Ordering::Relaxed);
+        self.buffer.not_full.notify_all();
+    }
+}
+
+impl AccountsDb {
+    /// Subscribe to writes to `account_id`, buffering at most `capacity`
+    /// updates.
+    pub fn subscribe(
+        &self,
+        account_id: AccountId,
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> Subscription {
+        assert!(capacity > 0, "subscriptions need room for one update");
+
+        let buffer = Arc::new(SubscriptionBuffer {
+            capacity,
+            policy,
+            updates: Mutex::new(VecDeque::with_capacity(capacity)),
+            not_full: Condvar::new(),
+            closed: AtomicBool::new(false),
+        });
+        self.subscriptions
+            .entry(account_id)
+            .or_default()
+            .push(Arc::downgrade(&buffer));
+
+        Subscription { buffer }
+    }
+
+    pub(super) fn publish(&self, account_id: AccountId, slot: Slot, account: &Account) {
+        let buffers: Vec<Arc<SubscriptionBuffer>> = {
+            let Some(mut subscribers) = self.subscriptions.get_mut(&account_id) else {
+                return;
+            };
+            subscribers.retain(|subscriber| subscriber.strong_count() > 0);
+
+            subscribers.iter().filter_map(Weak::upgrade).collect()
+        };
+
+        // pushing can block, so not while holding the map's shard
+        for buffer in buffers {
+            buffer.push((slot, account.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_balance(accounts_db: &AccountsDb, ancestors: &[Slot], balance: u64) {
+        let (_, mut write_accounts) = accounts_db
+            .load_versioned_accounts(&[], &[1])
+            .expect("load");
+        write_accounts[0].load_account(ancestors).unwrap().balance = balance;
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let accounts_db = AccountsDb::genesis_database();
+        let subscription = accounts_db.subscribe(1, 2, OverflowPolicy::DropOldest);
+
+        write_balance(&accounts_db, &[0, 1], 10);
+        write_balance(&accounts_db, &[0, 1, 2], 20);
+        write_balance(&accounts_db, &[0, 1, 2, 3], 30);
+
+        let balances: Vec<(Slot, u64)> = std::iter::from_fn(|| subscription.try_recv())
+            .map(|(slot, account)| (slot, account.balance))
+            .collect();
+        assert_eq!(balances, vec![(2, 20), (3, 30)]);
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        let accounts_db = AccountsDb::genesis_database();
+        let subscription = accounts_db.subscribe(1, 1, OverflowPolicy::DropNewest);
+
+        write_balance(&accounts_db, &[0, 1], 10);
+        write_balance(&accounts_db, &[0, 1, 2], 20);
+
+        assert_eq!(subscription.try_recv().unwrap().1.balance, 10);
+        assert_eq!(subscription.try_recv(), None);
+    }
+
+    #[test]
+    fn test_block() {
+        let accounts_db = Arc::new(AccountsDb::genesis_database());
+        let subscription = accounts_db.subscribe(1, 1, OverflowPolicy::Block);
+
+        let writer = {
+            let accounts_db = accounts_db.clone();
+            std::thread::spawn(move || {
+                write_balance(&accounts_db, &[0, 1], 10);
+                write_balance(&accounts_db, &[0, 1, 2], 20);
+            })
+        };
+
+        let mut balances = Vec::new();
+        while balances.len() < 2 {
+            if let Some((_, account)) = subscription.try_recv() {
+                balances.push(account.balance);
+            }
+        }
+        writer.join().unwrap();
+
+        assert_eq!(balances, vec![10, 20]);
+    }
+
+    #[test]
+    fn test_block_until_dropped() {
+        let accounts_db = Arc::new(AccountsDb::genesis_database());
+        let subscription = accounts_db.subscribe(1, 1, OverflowPolicy::Block);
+
+        let writer = {
+            let accounts_db = accounts_db.clone();
+            std::thread::spawn(move || {
+                write_balance(&accounts_db, &[0, 1], 10);
+                write_balance(&accounts_db, &[0, 1, 2], 20);
+            })
+        };
+        // give the writer time to block on the full buffer
+        std::thread::sleep(std::time::Duration::from_millis(50));
+
+        // a blocked writer doesn't hold up the rest of the subscriptions map
+        let other = accounts_db.subscribe(1, 2, OverflowPolicy::DropNewest);
+        // and no longer has to wait once the subscriber is gone
+        drop(subscription);
+        writer.join().unwrap();
+
+        write_balance(&accounts_db, &[0, 1, 2, 3], 30);
+        let (slot, account) = std::iter::from_fn(|| other.try_recv()).last().unwrap();
+        assert_eq!((slot, account.balance), (3, 30));
+    }
+}
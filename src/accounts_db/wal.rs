@@ -0,0 +1,146 @@
+//! An optional in-memory write-ahead log. When enabled, every write made
+//! through a `WriteAccount` is appended as the account's value in the written
+//! slot when the `WriteAccount` is dropped. Replaying the log on top of the
+//! snapshot it was started from rebuilds the inflight state.
+
+use super::*;
+
+use std::sync::Mutex;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WalEntry {
+    pub slot: Slot,
+    pub account_id: AccountId,
+    pub account: Account,
+    /// Whether this write created the account on its fork.
+    pub created: bool,
+}
+
+#[derive(Default)]
+pub(super) struct Wal {
+    entries: Mutex<Vec<WalEntry>>,
+}
+
+impl Wal {
+    pub(super) fn append(&self, entry: WalEntry) {
+        self.entries.lock().unwrap().push(entry);
+    }
+
+    pub(super) fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+impl AccountsDb {
+    /// Start logging writes to a fresh WAL.
+    pub fn with_wal(mut self) -> Self {
+        self.wal = Some(Wal::default());
+        self
+    }
+
+    /// Everything logged so far, oldest first. Empty if the WAL is disabled.
+    pub fn wal_entries(&self) -> Vec<WalEntry> {
+        self.wal
+            .as_ref()
+            .map(|wal| wal.entries.lock().unwrap().clone())
+            .unwrap_or_default()
+    }
+
+    /// Apply `entries` in order as inflight updates.
+    pub fn replay_wal(&self, entries: &[WalEntry]) -> Result<(), LoadError> {
+        for entry in entries {
+            let (_, mut write_accounts) = self.load_versioned_accounts(&[], &[entry.account_id])?;
+            // the entry already says whether it created the account, which
+            // `set_account` can't tell without the fork
+            write_accounts[0].record_write(entry.slot);
+            if entry.created {
+                self.account_counts.lock().unwrap().add_created(entry.slot);
+            }
+            write_accounts[0]
+                .deref_mut()
+                .set_account(entry.account.clone(), entry.slot);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_wal() {
+        let accounts_db = AccountsDb::genesis_database().with_wal();
+
+        let mut snapshot = Vec::new();
+        accounts_db.write_snapshot(&mut snapshot).expect("write");
+
+        for ancestors in [&[0, 1][..], &[0, 1, 2], &[0, 3]] {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[0, 1])
+                .expect("load");
+            write_accounts[0].load_account(ancestors).unwrap().balance -= 10;
+            write_accounts[0].load_account(ancestors).unwrap().balance -= 1;
+            write_accounts[1].load_account(ancestors).unwrap().balance += 11;
+        }
+
+        let entries = accounts_db.wal_entries();
+        assert_eq!(entries.len(), 6);
+
+        let recovered = AccountsDb::read_snapshot(&mut snapshot.as_slice()).expect("read");
+        recovered.replay_wal(&entries).expect("replay");
+        // account 1 was created separately on both forks
+        for ancestors in [&[0, 1][..], &[0, 1, 2], &[0, 3]] {
+            assert_eq!(recovered.account_count(ancestors), 2);
+            assert_eq!(
+                recovered.account_count(ancestors),
+                accounts_db.account_count(ancestors)
+            );
+        }
+
+        for account_id in [0, 1] {
+            for ancestors in [&[0, 1][..], &[0, 1, 2], &[0, 3]] {
+                assert_eq!(
+                    recovered
+                        .get_versioned_account(account_id)
+                        .unwrap()
+                        .get_account(ancestors),
+                    accounts_db
+                        .get_versioned_account(account_id)
+                        .unwrap()
+                        .get_account(ancestors)
+                );
+            }
+        }
+        assert!(recovered.wal_entries().is_empty());
+    }
+
+    #[test]
+    fn test_replay_after_reset() {
+        let accounts_db = AccountsDb::genesis_database().with_wal();
+        let write = |to: AccountId, amount: u64| {
+            let (_, mut write_accounts) = accounts_db
+                .load_versioned_accounts(&[], &[0, to])
+                .expect("load");
+            write_accounts[0].load_account(&[0, 1]).unwrap().balance -= amount;
+            write_accounts[1].load_account(&[0, 1]).unwrap().balance += amount;
+        };
+        write(1, 10);
+
+        accounts_db.reset_to_genesis();
+        assert!(accounts_db.wal_entries().is_empty());
+        write(2, 20);
+
+        // replaying onto genesis only brings back what came after the reset
+        let recovered = AccountsDb::genesis_database();
+        recovered
+            .replay_wal(&accounts_db.wal_entries())
+            .expect("replay");
+        assert_eq!(
+            recovered.read_many(&[0, 1, 2], &[0, 1]),
+            accounts_db.read_many(&[0, 1, 2], &[0, 1])
+        );
+        assert_eq!(recovered.read_many(&[1], &[0, 1]), vec![None]);
+    }
+}
@@ -0,0 +1,96 @@
+//! Every bank that hasn't been pruned, keyed by slot. Rooting through
+//! `BankForks` rather than `Bank::finalize` checks that the chain being
+//! finalized is one we actually built.
+
+use super::*;
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, PartialEq)]
+pub enum RootError {
+    /// No bank is tracked at this slot.
+    UnknownSlot(Slot),
+    /// An ancestor above the current root isn't tracked, or is tracked with
+    /// a different chain than the one leading to the new root.
+    MissingAncestor(Slot),
+}
+
+#[derive(Default)]
+pub struct BankForks {
+    banks: BTreeMap<Slot, Bank>,
+}
+
+impl BankForks {
+    pub fn new(genesis: Bank) -> Self {
+        let mut bank_forks = Self::default();
+        bank_forks.insert(genesis);
+
+        bank_forks
+    }
+
+    pub fn insert(&mut self, bank: Bank) {
+        self.banks.insert(bank.slot, bank);
+    }
+
+    pub fn get(&self, slot: Slot) -> Option<&Bank> {
+        self.banks.get(&slot)
+    }
+
+    /// Finalize the chain ending at `slot`, after checking that a bank is
+    /// tracked there and at every ancestor above the current root, each with
+    /// the matching prefix of its ancestors.
+    pub fn set_root_checked(&self, slot: Slot) -> Result<(), RootError> {
+        let bank = self.get(slot).ok_or(RootError::UnknownSlot(slot))?;
+        let finalized_slot = bank.accounts_db.finalized_slot();
+
+        for (index, ancestor) in bank.ancestors.iter().enumerate() {
+            if *ancestor <= finalized_slot {
+                continue;
+            }
+            match self.get(*ancestor) {
+                Some(ancestor_bank) if ancestor_bank.ancestors == bank.ancestors[..=index] => {}
+                _ => return Err(RootError::MissingAncestor(*ancestor)),
+            }
+        }
+
+        bank.accounts_db.finalize(&bank.ancestors);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_root_checked() {
+        let bank_0 = Bank::genesis_bank();
+        let bank_1 = bank_0.new_from_parent(1);
+        let bank_2 = bank_1.new_from_parent(2);
+        let bank_3 = bank_2.new_from_parent(3);
+        let accounts_db = bank_0.accounts_db.clone();
+
+        let mut bank_forks = BankForks::new(bank_0);
+        bank_forks.insert(bank_1);
+        bank_forks.insert(bank_3);
+
+        assert_eq!(
+            bank_forks.set_root_checked(4),
+            Err(RootError::UnknownSlot(4))
+        );
+        // slot 2 was never tracked
+        assert_eq!(
+            bank_forks.set_root_checked(3),
+            Err(RootError::MissingAncestor(2))
+        );
+        assert_eq!(accounts_db.finalized_slot(), 0);
+
+        assert_eq!(bank_forks.set_root_checked(1), Ok(()));
+        assert_eq!(accounts_db.finalized_slot(), 1);
+
+        bank_forks.insert(bank_2);
+        assert_eq!(bank_forks.set_root_checked(3), Ok(()));
+        assert_eq!(accounts_db.finalized_slot(), 3);
+    }
+}
@@ -0,0 +1,57 @@
+//! `Bank` takes care of locking accounts and resolving them against its fork;
+//! an `Executor` decides what a transaction actually does to them. The
+//! accounts are handed over in the order of the write half of
+//! `Transaction::lock_set`, which for a transfer is `[from, to]`.
+
+use super::*;
+
+pub trait Executor: Send + Sync {
+    fn execute(
+        &self,
+        tx: &Transaction,
+        accounts: &mut [&mut Account],
+    ) -> Result<(), TransactionError>;
+
+    /// Whether `execute` only moves balances between the accounts it's given.
+    /// Builds with the `debug-invariants` feature check this on every
+    /// transaction.
+    fn conserves_balance(&self) -> bool {
+        false
+    }
+}
+
+/// Moves `tx.amount` of `tx.token` from `tx.from` to `tx.to`, failing if
+/// `tx.from` can't cover it. Neither account is left holding an empty entry
+/// for a non-native token, so states that only differ by empty entries hash
+/// the same.
+pub struct TransferExecutor;
+
+impl Executor for TransferExecutor {
+    fn execute(
+        &self,
+        tx: &Transaction,
+        accounts: &mut [&mut Account],
+    ) -> Result<(), TransactionError> {
+        let [from, to] = accounts else {
+            unreachable!("transfers load exactly two accounts");
+        };
+
+        if from.token_balance(tx.token) < tx.amount {
+            return Err(TransactionError::InsufficientFunds);
+        }
+        if tx.amount == 0 {
+            return Ok(());
+        }
+        *from.token_balance_mut(tx.token) -= tx.amount;
+        *to.token_balance_mut(tx.token) += tx.amount;
+        if from.token_balance(tx.token) == 0 {
+            from.tokens.remove(&tx.token);
+        }
+
+        Ok(())
+    }
+
+    fn conserves_balance(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,75 @@
+//! `AccountsDb` only tags updates with the slot they were written in, which
+//! says nothing about how slots relate to each other. `ForkGraph` records that
+//! lineage from banks' ancestor chains so the fork tree can be reconstructed.
+
+use super::*;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Default, Debug)]
+pub struct ForkGraph {
+    // the root maps to `None`
+    parents: BTreeMap<Slot, Option<Slot>>,
+}
+
+impl ForkGraph {
+    pub fn from_banks(banks: &[&Bank]) -> Self {
+        let mut fork_graph = Self::default();
+        for bank in banks {
+            fork_graph.add_chain(&bank.ancestors);
+        }
+
+        fork_graph
+    }
+
+    /// Record every parent link in `ancestors`, which is ordered like
+    /// `Bank::ancestors`.
+    pub fn add_chain(&mut self, ancestors: &[Slot]) {
+        let parents = std::iter::once(None).chain(ancestors.iter().copied().map(Some));
+        for (slot, parent) in ancestors.iter().zip(parents) {
+            self.parents.insert(*slot, parent);
+        }
+    }
+
+    /// One chain per leaf, ordered like `Bank::ancestors` (root first, leaf
+    /// last). Chains are sorted by leaf slot.
+    pub fn chains(&self) -> Vec<Vec<Slot>> {
+        let non_leaves: BTreeSet<Slot> = self.parents.values().flatten().copied().collect();
+
+        self.parents
+            .keys()
+            .filter(|slot| !non_leaves.contains(slot))
+            .map(|leaf| {
+                let mut chain = vec![*leaf];
+                while let Some(Some(parent)) = self.parents.get(chain.last().unwrap()) {
+                    chain.push(*parent);
+                }
+                chain.reverse();
+
+                chain
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chains() {
+        let bank_0 = Bank::genesis_bank();
+        let bank_1 = bank_0.new_from_parent(1);
+        let bank_2 = bank_1.new_from_parent(2);
+        let bank_3 = bank_1.new_from_parent(3);
+        let bank_4 = bank_0.new_from_parent(4);
+        let bank_5 = bank_3.new_from_parent(5);
+
+        let fork_graph = ForkGraph::from_banks(&[&bank_2, &bank_4, &bank_5, &bank_1]);
+
+        assert_eq!(
+            fork_graph.chains(),
+            vec![vec![0, 1, 2], vec![0, 4], vec![0, 1, 3, 5]]
+        );
+    }
+}
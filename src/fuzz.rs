@@ -0,0 +1,133 @@
+//! A bounded fuzz harness for finalize. Each seed grows a random fork tree
+//! with random transfers, roots a random live bank, and checks every bank that
+//! survives against a model of what its balances should be. Extra cases can be
+//! run by adding seeds to `SEED_CORPUS`.
+
+use super::*;
+
+use std::collections::HashMap;
+
+// seeds that have turned up bugs are worth keeping here
+const SEED_CORPUS: &[u64] = &[1, 2, 3, 42, 0xdead_beef, 0x5eed_5eed, u64::MAX];
+const ACCOUNTS: u64 = 8;
+const ROUNDS: usize = 6;
+const BANKS_PER_ROUND: usize = 8;
+const TRANSFERS_PER_BANK: usize = 4;
+
+/// xorshift64*, so runs are reproducible without pulling in a dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift gets stuck at 0
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+/// A bank next to the balances it should have.
+struct ModelBank {
+    bank: Bank,
+    balances: HashMap<AccountId, u64>,
+}
+
+impl ModelBank {
+    fn check(&self, seed: u64) {
+        let mut total = 0;
+        for account_id in 0..ACCOUNTS {
+            let balance = self.bank.get_account(account_id).map_or(0, |a| a.balance);
+            let expected = self.balances.get(&account_id).copied().unwrap_or_default();
+            assert_eq!(
+                balance, expected,
+                "seed {seed}: account {account_id} on slot {}",
+                self.bank.slot
+            );
+            total += balance;
+        }
+        assert_eq!(
+            total, GENESIS_SUPPLY,
+            "seed {seed}: supply on slot {}",
+            self.bank.slot
+        );
+    }
+}
+
+fn run(seed: u64) {
+    let mut rng = Rng::new(seed);
+    let genesis = Bank::genesis_bank();
+    let accounts_db = genesis.accounts_db.clone();
+    let mut banks = vec![ModelBank {
+        bank: genesis,
+        balances: HashMap::from([(0, GENESIS_SUPPLY)]),
+    }];
+    let mut next_slot = 1;
+
+    for _ in 0..ROUNDS {
+        for _ in 0..BANKS_PER_ROUND {
+            let parent = &banks[rng.below(banks.len() as u64) as usize];
+            let bank = parent.bank.new_from_parent(next_slot);
+            let mut balances = parent.balances.clone();
+            next_slot += 1 + rng.below(2);
+
+            // only ever write a bank before it has children, otherwise they'd
+            // see the writes too
+            for _ in 0..TRANSFERS_PER_BANK {
+                let from = rng.below(ACCOUNTS);
+                let to = rng.below(ACCOUNTS);
+                if from == to {
+                    assert!(
+                        bank.transfer_saturating(from, to, 1).is_err(),
+                        "seed {seed}"
+                    );
+                    continue;
+                }
+                let amount = rng.below(GENESIS_SUPPLY / 2);
+
+                let moved = bank
+                    .transfer_saturating(from, to, amount)
+                    .expect("transfer");
+                let from_balance = balances.entry(from).or_default();
+                assert_eq!(moved, amount.min(*from_balance), "seed {seed}");
+                *from_balance -= moved;
+                *balances.entry(to).or_default() += moved;
+            }
+
+            banks.push(ModelBank { bank, balances });
+        }
+
+        let root = &banks[rng.below(banks.len() as u64) as usize].bank;
+        let (root_slot, root_ancestors) = (root.slot, root.ancestors.clone());
+        if root_slot > accounts_db.finalized_slot() {
+            root.finalize();
+        }
+
+        // everything that doesn't descend from the root is dead
+        banks.retain(|model| model.bank.ancestors.contains(&root_slot));
+        for model in &banks {
+            model.check(seed);
+        }
+        assert_eq!(accounts_db.verify_all(), Ok(()), "seed {seed}");
+        assert_eq!(
+            accounts_db.orphaned_update_count(&root_ancestors),
+            0,
+            "seed {seed}"
+        );
+    }
+}
+
+#[test]
+fn test_fuzz_finalize() {
+    for seed in SEED_CORPUS {
+        run(*seed);
+    }
+}
@@ -1,44 +1,604 @@
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, OnceLock};
 
 pub const GENESIS_SUPPLY: u64 = 1_000_000;
 
 pub type AccountId = u64;
 pub type Slot = u64;
+pub type TokenId = u64;
+pub type TransactionId = [u8; 32];
+
+/// The ids of the transactions applied in each slot.
+type ProcessedTransactions = HashMap<Slot, HashSet<TransactionId>>;
+
+/// The largest `Account::data` that `Bank::realloc` will grow to.
+pub const MAX_DATA_LEN: usize = 10 * 1024 * 1024;
+
+/// The token held in `Account::balance`, which pays fees.
+pub const NATIVE_TOKEN_ID: TokenId = 0;
+
+/// Owner of the built-in sysvar accounts.
+pub const SYSVAR_OWNER_ID: AccountId = u64::MAX;
+/// Holds the slot of the bank that last wrote it in its balance.
+pub const CLOCK_ID: AccountId = u64::MAX - 1;
 
 pub mod accounts_db;
-use accounts_db::AccountsDb;
+use accounts_db::{
+    AccountsDb, AccountsDbConfig, FinalizeReport, LoadError, LockGuard, TransactionContext,
+};
+
+pub mod executor;
+use executor::{Executor, TransferExecutor};
+
+pub mod scheduler;
+
+pub mod mempool;
+
+pub mod fork_graph;
+
+pub mod bank_forks;
+
+pub mod read_only_bank;
+
+#[cfg(test)]
+mod fuzz;
+
+pub mod hash;
+use hash::{Sha256, StateHasher};
+
+pub mod merkle;
+use merkle::MerkleTree;
 
 #[derive(Clone, Debug, PartialEq, Default)]
 pub struct Account {
     pub balance: u64,
+    /// The program that controls this account; defaults to 0.
+    pub owner: AccountId,
+    /// Balances of every token other than the native one.
+    pub tokens: BTreeMap<TokenId, u64>,
+    /// Opaque bytes for the owner to interpret.
+    pub data: Vec<u8>,
+}
+
+impl Account {
+    pub fn token_balance(&self, token: TokenId) -> u64 {
+        if token == NATIVE_TOKEN_ID {
+            return self.balance;
+        }
+
+        self.tokens.get(&token).copied().unwrap_or_default()
+    }
+
+    pub fn token_balance_mut(&mut self, token: TokenId) -> &mut u64 {
+        if token == NATIVE_TOKEN_ID {
+            return &mut self.balance;
+        }
+
+        self.tokens.entry(token).or_default()
+    }
 }
 
-struct Transaction {
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Transaction {
     pub from: AccountId,
     pub to: AccountId,
     pub amount: u64,
+    /// Which token `amount` is denominated in.
+    pub token: TokenId,
+    pub nonce: u64,
+    /// Higher priority transactions are scheduled first.
+    pub priority: u64,
+}
+
+impl Transaction {
+    /// The size of `to_bytes`' output.
+    pub const ENCODED_LEN: usize = 6 * 8;
+
+    pub fn builder() -> TransactionBuilder {
+        TransactionBuilder::default()
+    }
+
+    /// The accounts applying this transaction locks, as `(read, write)`. The
+    /// write accounts are handed to the executor in this order, payer first.
+    pub fn lock_set(&self) -> (Vec<AccountId>, Vec<AccountId>) {
+        (vec![], vec![self.from, self.to])
+    }
+
+    /// `lock_set`, failing if it names an account twice: each account is
+    /// locked once and handed to the executor as its own `&mut`.
+    pub fn checked_lock_set(&self) -> Result<(Vec<AccountId>, Vec<AccountId>), TransactionError> {
+        let (read_account_ids, write_account_ids) = self.lock_set();
+        let account_ids = [read_account_ids.as_slice(), &write_account_ids].concat();
+        for (index, account_id) in account_ids.iter().enumerate() {
+            if account_ids[..index].contains(account_id) {
+                return Err(TransactionError::DuplicateAccount(*account_id));
+            }
+        }
+
+        Ok((read_account_ids, write_account_ids))
+    }
+
+    /// Every field as a little-endian `u64`, in declaration order.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.fields()
+            .iter()
+            .flat_map(|field| field.to_le_bytes())
+            .collect()
+    }
+
+    /// Decode the layout written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Transaction, DecodeError> {
+        if bytes.len() != Self::ENCODED_LEN {
+            return Err(DecodeError::WrongLength(bytes.len()));
+        }
+
+        let mut fields = bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()));
+        let mut next = || fields.next().unwrap();
+
+        Ok(Transaction {
+            from: next(),
+            to: next(),
+            amount: next(),
+            token: next(),
+            nonce: next(),
+            priority: next(),
+        })
+    }
+
+    fn fields(&self) -> [u64; 6] {
+        [
+            self.from,
+            self.to,
+            self.amount,
+            self.token,
+            self.nonce,
+            self.priority,
+        ]
+    }
+
+    /// SHA-256 over every field as a little-endian `u64`, so transactions
+    /// only share an id if they're identical. Vary `nonce` to repeat one.
+    pub fn id(&self) -> TransactionId {
+        let mut hasher = Sha256::default();
+        hasher.update(&self.to_bytes());
+
+        hasher.finalize()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// Encoded transactions are exactly `Transaction::ENCODED_LEN` bytes.
+    WrongLength(usize),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BuildError {
+    MissingField(&'static str),
+}
+
+/// Fluent constructor for `Transaction`. `from`, `to` and `amount` are
+/// required; `token` defaults to the native token and `nonce` and `priority`
+/// to 0.
+#[derive(Default)]
+pub struct TransactionBuilder {
+    from: Option<AccountId>,
+    to: Option<AccountId>,
+    amount: Option<u64>,
+    token: Option<TokenId>,
+    nonce: Option<u64>,
+    priority: Option<u64>,
+}
+
+impl TransactionBuilder {
+    pub fn from(mut self, from: AccountId) -> Self {
+        self.from = Some(from);
+        self
+    }
+
+    pub fn to(mut self, to: AccountId) -> Self {
+        self.to = Some(to);
+        self
+    }
+
+    pub fn amount(mut self, amount: u64) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    pub fn token(mut self, token: TokenId) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    pub fn nonce(mut self, nonce: u64) -> Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    pub fn priority(mut self, priority: u64) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn build(self) -> Result<Transaction, BuildError> {
+        Ok(Transaction {
+            from: self.from.ok_or(BuildError::MissingField("from"))?,
+            to: self.to.ok_or(BuildError::MissingField("to"))?,
+            amount: self.amount.ok_or(BuildError::MissingField("amount"))?,
+            token: self.token.unwrap_or(NATIVE_TOKEN_ID),
+            nonce: self.nonce.unwrap_or_default(),
+            priority: self.priority.unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TransactionError {
+    InsufficientFunds,
+    SlotNotInAncestors(Slot),
+    ImmutableAccount,
+    BankFrozen,
+    /// Growing account data past `MAX_DATA_LEN`.
+    DataTooLarge,
+    /// A transaction with the same id already landed on this fork.
+    AlreadyProcessed,
+    /// Creating an account that already resolves on this fork.
+    AccountAlreadyExists,
+    /// Under `ConflictPolicy::Reject`, writing an account that an earlier
+    /// transaction already wrote in the same slot. Under
+    /// `ConflictPolicy::Overwrite`, only writing some of the accounts written
+    /// earlier in the slot.
+    SlotConflict,
+    /// Writing an account outside the bank's write allowlist.
+    WriteNotPermitted,
+    /// A transaction naming the same account more than once.
+    DuplicateAccount(AccountId),
+    Load(LoadError),
+}
+
+impl From<LoadError> for TransactionError {
+    fn from(err: LoadError) -> Self {
+        match err {
+            LoadError::ImmutableAccount => Self::ImmutableAccount,
+            err => Self::Load(err),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MergeError {
+    /// Both forks wrote these accounts since they diverged.
+    Conflict(Vec<AccountId>),
+    Load(LoadError),
+}
+
+impl From<LoadError> for MergeError {
+    fn from(err: LoadError) -> Self {
+        Self::Load(err)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DeterminismError {
+    /// Two replays of the same log ended with different state hashes.
+    HashMismatch([u8; 32], [u8; 32]),
+    Transaction(TransactionError),
+}
+
+impl From<TransactionError> for DeterminismError {
+    fn from(err: TransactionError) -> Self {
+        Self::Transaction(err)
+    }
+}
+
+/// Emitted to a bank's event sink; see `Bank::with_event_sink`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BankEvent {
+    Transfer {
+        from: AccountId,
+        to: AccountId,
+        amount: u64,
+        token: TokenId,
+        slot: Slot,
+    },
+}
+
+/// What a transaction sees when an account it writes was already written
+/// earlier in the same slot; see `Bank::with_conflict_policy`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ConflictPolicy {
+    /// Run on top of the earlier write, so both take effect.
+    Accumulate,
+    /// Run against the accounts as of the parent slot, replacing everything
+    /// written earlier in the slot. Only allowed when the transaction writes
+    /// every account the slot has written, since rewinding some of an earlier
+    /// transaction's accounts but not others would create or destroy funds;
+    /// otherwise fails with `TransactionError::SlotConflict`.
+    #[default]
+    Overwrite,
+    /// Fail with `TransactionError::SlotConflict`.
+    Reject,
+}
+
+/// What `apply` charges the sender on top of the transfer. Fees are burned.
+/// The default schedule is free.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FeeSchedule {
+    pub base_fee: u64,
+    /// Charged once per unit of `Transaction::priority`.
+    pub fee_per_priority: u64,
+}
+
+impl FeeSchedule {
+    pub fn fee(&self, tx: &Transaction) -> u64 {
+        self.fee_per_priority
+            .saturating_mul(tx.priority)
+            .saturating_add(self.base_fee)
+    }
+}
+
+/// The result of `Bank::reorg_to`: the new head, plus what each fork did
+/// since the two diverged. Account updates are `(account_id, slot, account)`
+/// sorted by account id then slot, with sysvar writes left out; transactions
+/// are `(slot, tx_id)` sorted by slot then id, and only reported for banks
+/// with `Bank::with_finality_tracking`.
+pub struct Reorg {
+    pub bank: Bank,
+    /// Updates on the abandoned fork.
+    pub reverted: Vec<(AccountId, Slot, Account)>,
+    /// Updates on the fork being switched to.
+    pub applied: Vec<(AccountId, Slot, Account)>,
+    /// Transactions applied on the abandoned fork.
+    pub reverted_transactions: Vec<(Slot, TransactionId)>,
+    /// Transactions applied on the fork being switched to.
+    pub applied_transactions: Vec<(Slot, TransactionId)>,
+}
+
+/// How disruptive switching between two heads is; see `reorg_cost`.
+#[derive(Debug, PartialEq)]
+pub struct ReorgCost {
+    /// Slots on the abandoned fork since the common ancestor.
+    pub slots_rolled_back: usize,
+    /// Accounts that resolve differently on the two heads. Sysvars are left
+    /// out.
+    pub accounts_changed: usize,
+    /// The sum of how far each changed account's balance moves, in either
+    /// direction.
+    pub balance_moved: u64,
+}
+
+/// How far `Bank::apply_batch_best_effort` got.
+#[derive(Debug, PartialEq)]
+pub struct BatchResult {
+    /// Transactions applied, all from the front of the batch.
+    pub applied: usize,
+    /// Why the next transaction failed, if one did.
+    pub error: Option<TransactionError>,
+}
+
+/// What a transaction would do; see `Bank::simulate_batch`.
+#[derive(Debug, PartialEq)]
+pub struct SimulationResult {
+    pub fee: u64,
+    /// The transaction's write accounts as they'd be afterwards, in
+    /// `Transaction::lock_set` order.
+    pub accounts: Vec<(AccountId, Account)>,
+}
+
+/// How much native balance an account must hold to keep its data; the
+/// default is free.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rent {
+    pub per_byte: u64,
+}
+
+impl Rent {
+    pub fn minimum_balance(&self, data_len: usize) -> u64 {
+        self.per_byte.saturating_mul(data_len as u64)
+    }
+
+    pub fn below_minimum(&self, account: &Account) -> bool {
+        account.balance < self.minimum_balance(account.data.len())
+    }
 }
 
-struct Bank {
+#[derive(Clone)]
+pub struct Bank {
     pub slot: Slot,
     // the last ancestor is the slot of this bank
     pub ancestors: Vec<Slot>,
     pub accounts_db: Arc<AccountsDb>,
+    pub executor: Arc<dyn Executor>,
+    pub fee_schedule: FeeSchedule,
+    pub rent: Rent,
+    pub conflict_policy: ConflictPolicy,
+    /// Free-form tags, e.g. which leader produced the fork. Nothing reads
+    /// them; child banks get a copy.
+    pub metadata: HashMap<String, String>,
+    // accounts transactions may write, if restricted
+    write_allowlist: Option<Arc<HashSet<AccountId>>>,
+    // set once by `freeze`, shared with clones of this bank
+    frozen_hash: Arc<OnceLock<[u8; 32]>>,
+    // ids applied in each slot, shared by every bank descended from the one
+    // that enabled dedup
+    processed: Option<Arc<Mutex<ProcessedTransactions>>>,
+    // whether applied transactions are recorded for `finality_latency`
+    track_finality: bool,
+    event_sink: Option<Sender<BankEvent>>,
 }
 
+// banks are shared across worker threads, so keep them Send + Sync
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Bank>();
+};
+
 impl Bank {
     pub fn genesis_bank() -> Self {
+        Self::genesis_bank_with_config(AccountsDbConfig::default())
+    }
+
+    pub fn genesis_bank_with_config(config: AccountsDbConfig) -> Self {
+        let accounts_db = AccountsDb::genesis_database_with_config(config);
+        accounts_db.pin(CLOCK_ID);
+
         Self {
             slot: 0,
             ancestors: vec![0],
-            accounts_db: Arc::new(AccountsDb::genesis_database()),
+            accounts_db: Arc::new(accounts_db),
+            executor: Arc::new(TransferExecutor),
+            fee_schedule: FeeSchedule::default(),
+            rent: Rent::default(),
+            conflict_policy: ConflictPolicy::default(),
+            metadata: HashMap::new(),
+            write_allowlist: None,
+            frozen_hash: Arc::default(),
+            processed: None,
+            track_finality: false,
+            event_sink: None,
+        }
+    }
+
+    /// Run transactions through `executor` instead of the default transfer.
+    /// Child banks inherit it.
+    pub fn with_executor(mut self, executor: Arc<dyn Executor>) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    /// Charge fees according to `fee_schedule`. Child banks inherit it.
+    pub fn with_fee_schedule(mut self, fee_schedule: FeeSchedule) -> Self {
+        self.fee_schedule = fee_schedule;
+        self
+    }
+
+    /// Require accounts to cover `rent` for their data. Child banks inherit
+    /// it.
+    pub fn with_rent(mut self, rent: Rent) -> Self {
+        self.rent = rent;
+        self
+    }
+
+    /// Decide how transactions treat same-slot writes by earlier ones; see
+    /// `ConflictPolicy`. Only applied transactions follow it, not direct
+    /// writes like `transfer`. Child banks inherit it.
+    pub fn with_conflict_policy(mut self, conflict_policy: ConflictPolicy) -> Self {
+        self.conflict_policy = conflict_policy;
+        self
+    }
+
+    /// Tag this bank with `key = value`, replacing any earlier value. Child
+    /// banks inherit it.
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Fail applied transactions that would write any account outside
+    /// `account_ids`, fee payers included, with
+    /// `TransactionError::WriteNotPermitted`. Child banks inherit it.
+    pub fn with_write_allowlist(
+        mut self,
+        account_ids: impl IntoIterator<Item = AccountId>,
+    ) -> Self {
+        self.write_allowlist = Some(Arc::new(account_ids.into_iter().collect()));
+        self
+    }
+
+    fn check_write_allowed(&self, write_account_ids: &[AccountId]) -> Result<(), TransactionError> {
+        let Some(allowlist) = &self.write_allowlist else {
+            return Ok(());
+        };
+        if !write_account_ids
+            .iter()
+            .all(|account_id| allowlist.contains(account_id))
+        {
+            return Err(TransactionError::WriteNotPermitted);
         }
+
+        Ok(())
+    }
+
+    /// Reject transactions whose `id` already landed on the same fork with
+    /// `TransactionError::AlreadyProcessed`. Child banks inherit it; sibling
+    /// forks can still each apply the same transaction. Ids are never pruned,
+    /// so memory grows with every transaction applied.
+    pub fn with_dedup(mut self) -> Self {
+        self.processed = Some(Arc::default());
+        self
+    }
+
+    /// Record where each applied transaction lands, for
+    /// `AccountsDb::finality_latency` and the transactions in a `Reorg`. Off
+    /// by default, since it takes a db-wide lock on every apply. Child banks
+    /// inherit it.
+    pub fn with_finality_tracking(mut self) -> Self {
+        self.track_finality = true;
+        self
+    }
+
+    /// Send a `BankEvent` to `sink` for every transaction that applies
+    /// successfully, in the slot it landed in. Child banks inherit it. Events
+    /// are dropped once the receiver hangs up.
+    pub fn with_event_sink(mut self, sink: Sender<BankEvent>) -> Self {
+        self.event_sink = Some(sink);
+        self
+    }
+
+    /// The fee `apply` would charge for `tx`, without applying it.
+    pub fn estimate_fee(&self, tx: &Transaction) -> u64 {
+        self.fee_schedule.fee(tx)
     }
 
     pub fn get_account(&self, account_id: AccountId) -> Option<Account> {
         let stored_account = self.accounts_db.get_versioned_account(account_id)?;
 
-        stored_account.get_account(&self.ancestors).cloned()
+        stored_account
+            .get_account_with_root(&self.ancestors, self.accounts_db.finalized_slot())
+            .cloned()
+    }
+
+    /// `account_id`'s balance resolved against `ancestors[..=index]`, i.e. as
+    /// of that earlier point on this fork. History below the finalized slot
+    /// is gone, so those indexes all see the finalized balance.
+    pub fn balance_at_ancestor_index(&self, account_id: AccountId, index: usize) -> Option<u64> {
+        let ancestors = self.ancestors.get(..=index)?;
+        let stored_account = self.accounts_db.get_versioned_account(account_id)?;
+
+        stored_account
+            .get_account_with_root(ancestors, self.accounts_db.finalized_slot())
+            .map(|account| account.balance)
+    }
+
+    /// Each slot on this fork where `account_id`'s balance changed, with the
+    /// balance from then on. Starts from the finalized balance, so changes
+    /// that were already rooted don't show up.
+    pub fn balance_trajectory(&self, account_id: AccountId) -> Vec<(Slot, u64)> {
+        let Some(stored_account) = self.accounts_db.get_versioned_account(account_id) else {
+            return Vec::new();
+        };
+
+        let mut balance = stored_account
+            .get_account(&[])
+            .map(|account| account.balance);
+        let mut trajectory = Vec::new();
+        for slot in &self.ancestors {
+            let Some(account) = stored_account.get_account_ex(&[*slot], false) else {
+                continue;
+            };
+            if balance != Some(account.balance) {
+                balance = Some(account.balance);
+                trajectory.push((*slot, account.balance));
+            }
+        }
+
+        trajectory
     }
 
     pub fn new_from_parent(&self, slot: Slot) -> Self {
@@ -46,133 +606,3111 @@ impl Bank {
 
         ancestors.push(slot);
 
+        self.accounts_db
+            .set_sysvar(
+                CLOCK_ID,
+                Account {
+                    balance: slot,
+                    owner: SYSVAR_OWNER_ID,
+                    ..Default::default()
+                },
+                &ancestors,
+            )
+            .expect("ancestors are never empty");
+
         Self {
             slot,
             ancestors,
             accounts_db: self.accounts_db.clone(),
+            executor: self.executor.clone(),
+            fee_schedule: self.fee_schedule,
+            rent: self.rent,
+            conflict_policy: self.conflict_policy,
+            metadata: self.metadata.clone(),
+            write_allowlist: self.write_allowlist.clone(),
+            frozen_hash: Arc::default(),
+            processed: self.processed.clone(),
+            track_finality: self.track_finality,
+            event_sink: self.event_sink.clone(),
         }
     }
 
-    pub fn finalize(&self) {
-        self.accounts_db.finalize(&self.ancestors);
+    /// The slot of the bank this one was created from; `None` for genesis.
+    pub fn parent_slot(&self) -> Option<Slot> {
+        self.ancestors.iter().rev().nth(1).copied()
     }
 
-    pub fn apply(&self, tx: &Transaction) {
-        let (_, mut write_accounts) = self
+    /// How many accounts exist on this fork, without scanning them. Sysvars
+    /// like the clock aren't counted.
+    pub fn account_count(&self) -> u64 {
+        let sysvars = self
             .accounts_db
-            .load_versioned_accounts(&[], &[tx.from, tx.to])
-            .expect("load accounts");
+            .pinned()
+            .into_iter()
+            .filter(|account_id| self.get_account(*account_id).is_some())
+            .count();
 
-        // we need to do this because we need to borrow mutably twice
-        let (from_slice, to_slice) = write_accounts.split_at_mut(1);
-        let from = from_slice[0].load_account(&self.ancestors);
-        let to = to_slice[0].load_account(&self.ancestors);
+        self.accounts_db.account_count(&self.ancestors) - sysvars as u64
+    }
 
-        from.balance -= tx.amount;
-        to.balance += tx.amount;
+    /// The slot according to the clock sysvar, which `new_from_parent` keeps
+    /// up to date. Genesis has no clock account and reads as slot 0.
+    pub fn clock(&self) -> Slot {
+        self.get_account(CLOCK_ID).map_or(0, |clock| clock.balance)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Lock accounts on this fork; see `LockGuard`.
+    pub fn lock_accounts(
+        &self,
+        read_account_ids: &[AccountId],
+        write_account_ids: &[AccountId],
+    ) -> Result<LockGuard<'_>, TransactionError> {
+        Ok(self
+            .accounts_db
+            .lock_accounts(&self.ancestors, read_account_ids, write_account_ids)?)
+    }
 
-    #[test]
-    fn test_get_account() {
-        let bank = Bank::genesis_bank();
+    /// Start a transaction against this fork that may create
+    /// `account_ids`; see `TransactionContext`.
+    pub fn transaction_context(
+        &self,
+        account_ids: &[AccountId],
+    ) -> Result<TransactionContext<'_>, TransactionError> {
+        Ok(TransactionContext::new(
+            &self.accounts_db,
+            &self.ancestors,
+            account_ids,
+        )?)
+    }
 
-        assert_eq!(bank.slot, 0);
-        assert_eq!(bank.ancestors, vec![0]);
+    /// Throw away this bank's own write to `account_id`, so it resolves to
+    /// whatever the parent chain says again. Writes made by ancestors stay.
+    pub fn reset_account(&self, account_id: AccountId) -> Result<(), TransactionError> {
+        let (_, mut write_accounts) = self
+            .accounts_db
+            .load_versioned_accounts(&[], &[account_id])?;
+        write_accounts[0].discard_update(&self.ancestors);
 
-        assert_eq!(
-            bank.get_account(0),
-            Some(Account {
-                balance: GENESIS_SUPPLY
-            })
-        );
-        assert_eq!(bank.get_account(1), None);
+        Ok(())
     }
 
-    #[test]
-    fn test_apply() {
-        let bank_0 = Bank::genesis_bank();
+    /// Resize `account_id`'s data to `new_len`. Growing fails past
+    /// `MAX_DATA_LEN` or if the balance can't cover rent for the new length;
+    /// shrinking always succeeds and lowers the balance rent requires. Grown
+    /// data is always zeroed.
+    pub fn realloc(&self, account_id: AccountId, new_len: usize) -> Result<(), TransactionError> {
+        if self.is_frozen() {
+            return Err(TransactionError::BankFrozen);
+        }
 
-        let tx = Transaction {
-            from: 0,
-            to: 1,
-            amount: 42,
+        let (_, mut write_accounts) = self
+            .accounts_db
+            .load_versioned_accounts(&[], &[account_id])?;
+        // check before loading for writing, so a rejected grow doesn't
+        // create the account
+        let account = write_accounts[0]
+            .get_account(&self.ancestors)
+            .unwrap_or(self.accounts_db.default_account());
+        if new_len > account.data.len() {
+            if new_len > MAX_DATA_LEN {
+                return Err(TransactionError::DataTooLarge);
+            }
+            if account.balance < self.rent.minimum_balance(new_len) {
+                return Err(TransactionError::InsufficientFunds);
+            }
+        }
+
+        write_accounts[0]
+            .load_account(&self.ancestors)?
+            .data
+            .resize(new_len, 0);
+
+        Ok(())
+    }
+
+    /// Bring `other`'s changes since the two forks diverged into this bank's
+    /// slot. Fails without changing anything if both forks wrote the same
+    /// account. Pinned sysvars are skipped, so this bank keeps its own clock.
+    pub fn try_merge(&self, other: &Bank) -> Result<(), MergeError> {
+        let common = self.common_ancestors_len(other);
+        let modified_since_common = |bank: &Bank| -> Vec<AccountId> {
+            self.accounts_db
+                .accounts_modified_in_any(&bank.ancestors[common..])
+                .into_iter()
+                .filter(|account_id| !self.accounts_db.is_pinned(*account_id))
+                .collect()
         };
 
-        bank_0.apply(&tx);
+        let ours = modified_since_common(self);
+        let theirs = modified_since_common(other);
 
-        assert_eq!(bank_0.get_account(0).unwrap().balance, GENESIS_SUPPLY - 42);
-        assert_eq!(bank_0.get_account(1).unwrap().balance, 42);
+        let conflicts: Vec<AccountId> = theirs
+            .iter()
+            .filter(|account_id| ours.contains(account_id))
+            .copied()
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(MergeError::Conflict(conflicts));
+        }
 
-        let bank_1 = bank_0.new_from_parent(1);
+        let (_, mut write_accounts) = self.accounts_db.load_versioned_accounts(&[], &theirs)?;
+        for write_account in write_accounts.iter_mut() {
+            let merged = write_account
+                .get_account(&other.ancestors)
+                .cloned()
+                .expect("modified on the other fork");
+            *write_account.load_account(&self.ancestors)? = merged;
+        }
 
-        assert_eq!(bank_1.get_account(0).unwrap().balance, GENESIS_SUPPLY - 42);
-        assert_eq!(bank_1.get_account(1).unwrap().balance, 42);
+        Ok(())
+    }
 
-        let tx = Transaction {
-            from: 1,
-            to: 0,
-            amount: 10,
+    /// Switch from this head to `target`. Forks don't share inflight state, so
+    /// the new head is just `target`; what the reorg adds is the diff between
+    /// the two forks since their common ancestor.
+    pub fn reorg_to(&self, target: &Bank) -> Reorg {
+        let common = self.common_ancestors_len(target);
+        let updates_since_common = |bank: &Bank| -> Vec<(AccountId, Slot, Account)> {
+            self.accounts_db
+                .fork_updates(&bank.ancestors[common..])
+                .into_iter()
+                .filter(|(account_id, _, _)| !self.accounts_db.is_pinned(*account_id))
+                .collect()
         };
 
-        bank_1.apply(&tx);
+        let transactions_since_common = |bank: &Bank| {
+            self.accounts_db
+                .transactions_applied_in(&bank.ancestors[common..])
+        };
 
-        assert_eq!(bank_1.get_account(0).unwrap().balance, GENESIS_SUPPLY - 32);
-        assert_eq!(bank_1.get_account(1).unwrap().balance, 32);
+        Reorg {
+            bank: target.clone(),
+            reverted: updates_since_common(self),
+            applied: updates_since_common(target),
+            reverted_transactions: transactions_since_common(self),
+            applied_transactions: transactions_since_common(target),
+        }
+    }
 
-        assert_eq!(bank_0.get_account(0).unwrap().balance, GENESIS_SUPPLY - 42);
-        assert_eq!(bank_0.get_account(1).unwrap().balance, 42);
+    /// The first of this bank's ancestors that leaves `finalized_chain`, or
+    /// `None` if the bank is on the chain or descends from its tip. A bank
+    /// that diverged can never be rooted.
+    pub fn divergence_slot(&self, finalized_chain: &[Slot]) -> Option<Slot> {
+        self.ancestors
+            .iter()
+            .zip(finalized_chain)
+            .find(|(ours, rooted)| ours != rooted)
+            .map(|(ours, _)| *ours)
+    }
 
-        // a competing fork
-        let bank_2 = bank_0.new_from_parent(2);
+    /// How many leading ancestors this bank shares with `other`.
+    fn common_ancestors_len(&self, other: &Bank) -> usize {
+        self.ancestors
+            .iter()
+            .zip(&other.ancestors)
+            .take_while(|(ours, theirs)| ours == theirs)
+            .count()
+    }
 
-        // 0 is a double-spender :)
-        let tx = Transaction {
-            from: 0,
-            to: 1,
-            amount: 1,
-        };
+    /// Accounts this fork has written since the last finalize, sorted by id.
+    /// Sysvars count too, so any child bank has at least its clock dirty.
+    pub fn dirty_accounts(&self) -> Vec<AccountId> {
+        let mut account_ids = self.accounts_db.accounts_modified_in_any(&self.ancestors);
+        account_ids.retain(|account_id| !self.accounts_db.is_pinned(*account_id));
 
-        bank_2.apply(&tx);
+        account_ids
+    }
 
-        assert_eq!(bank_2.get_account(0).unwrap().balance, GENESIS_SUPPLY - 43);
-        assert_eq!(bank_2.get_account(1).unwrap().balance, 43);
+    /// Every account that resolves on this fork, in no particular order.
+    /// Sysvars like the clock are bookkeeping rather than state, so they're
+    /// left out here and in everything built on it: `export_state`,
+    /// `state_hash` and `merkle_root`.
+    pub fn accounts_iter(&self) -> impl Iterator<Item = (AccountId, Account)> {
+        let mut accounts = self.accounts_db.scan_accounts(&self.ancestors);
+        accounts.retain(|(account_id, _)| !self.accounts_db.is_pinned(*account_id));
 
-        assert_eq!(bank_1.get_account(0).unwrap().balance, GENESIS_SUPPLY - 32);
-        assert_eq!(bank_1.get_account(1).unwrap().balance, 32);
+        accounts.into_iter()
+    }
 
-        bank_2.finalize();
+    /// Every account that resolves on this fork, keyed by id.
+    pub fn export_state(&self) -> BTreeMap<AccountId, Account> {
+        self.accounts_iter().collect()
+    }
 
-        assert_eq!(bank_1.get_account(0).unwrap().balance, GENESIS_SUPPLY - 43);
-        assert_eq!(bank_1.get_account(1).unwrap().balance, 43);
+    pub fn accounts_by_owner(&self, owner: AccountId) -> Vec<(AccountId, Account)> {
+        self.accounts_iter()
+            .filter(|(_, account)| account.owner == owner)
+            .collect()
     }
 
-    //#[test]
-    //fn test_benchmark() {
-    //    let bank = Bank::genesis_bank();
+    pub fn total_balance_by_owner(&self, owner: AccountId) -> u64 {
+        self.accounts_by_owner(owner)
+            .iter()
+            .map(|(_, account)| account.balance)
+            .sum()
+    }
 
-    //    let tx = Transaction {
-    //        from: 0,
-    //        to: 1,
-    //        amount: 1,
-    //    };
+    /// SHA-256 over every account visible on this fork; see `state_hash_with`.
+    pub fn state_hash(&self) -> [u8; 32] {
+        self.state_hash_with::<Sha256>()
+    }
 
-    //    let mut total = 0;
+    /// Hash `(id, balance, owner, token_count, (token, balance)..., data_len,
+    /// data)` of every account visible on this fork, in ascending id order,
+    /// each field but `data` as a little-endian `u64`. Tokens are in
+    /// ascending id order too.
+    pub fn state_hash_with<H: StateHasher>(&self) -> [u8; 32] {
+        let mut hasher = H::default();
+        for (account_id, account) in self.export_state() {
+            hasher.update(&account_id.to_le_bytes());
+            hasher.update(&account.balance.to_le_bytes());
+            hasher.update(&account.owner.to_le_bytes());
+            hasher.update(&(account.tokens.len() as u64).to_le_bytes());
+            for (token, balance) in &account.tokens {
+                hasher.update(&token.to_le_bytes());
+                hasher.update(&balance.to_le_bytes());
+            }
+            hasher.update(&(account.data.len() as u64).to_le_bytes());
+            hasher.update(&account.data);
+        }
 
-    //    let start = std::time::Instant::now();
+        hasher.finalize()
+    }
 
-    //    for _ in 0..1_000_000 {
-    //        bank.apply(&tx);
-    //    }
+    /// The root of a `MerkleTree` over the `(id, balance)` of every account
+    /// visible on this fork, in ascending id order. Unlike `state_hash` it
+    /// only covers balances.
+    pub fn merkle_root(&self) -> [u8; 32] {
+        MerkleTree::new(
+            self.export_state()
+                .into_iter()
+                .map(|(account_id, account)| (account_id, account.balance)),
+        )
+        .root()
+    }
 
-    //    println!("elapsed millis: {}", start.elapsed().as_millis());
-    //}
-}
+    /// Create a bank at `to_slot` as if every slot in between was skipped.
+    /// The skipped slots never appear in `ancestors`, which is fine because
+    /// lookups only ever match the slots that are present.
+    pub fn advance_empty(&self, to_slot: Slot) -> Self {
+        assert!(to_slot > self.slot, "can only advance to a later slot");
 
-fn main() {
-    println!("Hello, world!");
+        self.new_from_parent(to_slot)
+    }
+
+    /// Create `n` banks in a row after this one, at the next `n` slots, and
+    /// return the last. With `n == 0` that's a clone of this bank.
+    pub fn extend_chain(&self, n: u64) -> Self {
+        (self.slot + 1..=self.slot + n).fold(self.clone(), |bank, slot| bank.new_from_parent(slot))
+    }
+
+    /// Stop this bank from executing any more transactions and record its
+    /// state hash. Freezing again returns the hash from the first time.
+    pub fn freeze(&self) -> [u8; 32] {
+        *self.frozen_hash.get_or_init(|| self.state_hash())
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen_hash.get().is_some()
+    }
+
+    /// Save a snapshot of the db to `dir` named `<slot>-<hash>.snapshot`, but
+    /// only if this bank is frozen and is the finalized root, so nothing it
+    /// shows can still change. Only what this bank sees is written; inflight
+    /// updates on other forks are left out. Returns the path written, if any.
+    pub fn persist_if_frozen(&self, dir: &Path) -> io::Result<Option<PathBuf>> {
+        let Some(hash) = self.frozen_hash.get() else {
+            return Ok(None);
+        };
+        if self.slot != self.accounts_db.finalized_slot() {
+            return Ok(None);
+        }
+
+        let hash: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+        let path = dir.join(format!("{}-{hash}.snapshot", self.slot));
+        self.accounts_db
+            .save_fork_snapshot(&path, &self.ancestors)?;
+
+        Ok(Some(path))
+    }
+
+    pub fn finalize(&self) -> FinalizeReport {
+        self.accounts_db.finalize(&self.ancestors)
+    }
+
+    /// Root this bank's ancestors except for the most recent `lockout`, which
+    /// stay inflight.
+    pub fn finalize_with_lockout(&self, lockout: u64) -> FinalizeReport {
+        self.accounts_db
+            .finalize_with_lockout(&self.ancestors, lockout)
+    }
+
+    /// Root this bank's ancestors up to and including `slot`, leaving the
+    /// later ones inflight.
+    pub fn finalize_through_slot(&self, slot: Slot) -> Result<FinalizeReport, TransactionError> {
+        let index = self
+            .ancestors
+            .iter()
+            .position(|ancestor| *ancestor == slot)
+            .ok_or(TransactionError::SlotNotInAncestors(slot))?;
+
+        Ok(self.accounts_db.finalize(&self.ancestors[..=index]))
+    }
+
+    pub fn apply(&self, tx: &Transaction) {
+        self.apply_with_ancestors(tx, &self.ancestors, tx.from)
+            .expect("apply transaction");
+    }
+
+    /// Apply `tx` and return the resulting `state_hash`.
+    pub fn apply_and_hash(&self, tx: &Transaction) -> Result<[u8; 32], TransactionError> {
+        self.apply_with_ancestors(tx, &self.ancestors, tx.from)?;
+
+        Ok(self.state_hash())
+    }
+
+    /// Apply `tx` with its fee debited from `fee_payer` instead of `tx.from`,
+    /// which still pays the amount. The fee payer is locked along with the
+    /// transaction's accounts, and if either debit can't be covered nothing
+    /// changes.
+    pub fn apply_sponsored(
+        &self,
+        tx: &Transaction,
+        fee_payer: AccountId,
+    ) -> Result<(), TransactionError> {
+        self.apply_with_ancestors(tx, &self.ancestors, fee_payer)
+    }
+
+    /// Apply `txs` in order, stopping at the first failure. The transactions
+    /// before it stay applied; the failed one and everything after it are
+    /// left out.
+    pub fn apply_batch_best_effort(&self, txs: &[Transaction]) -> BatchResult {
+        let mut applied = 0;
+        for tx in txs {
+            if let Err(err) = self.apply_with_ancestors(tx, &self.ancestors, tx.from) {
+                return BatchResult {
+                    applied,
+                    error: Some(err),
+                };
+            }
+            applied += 1;
+        }
+
+        BatchResult {
+            applied,
+            error: None,
+        }
+    }
+
+    /// Apply `tx` as if it landed in `slot`, an earlier slot on this fork.
+    /// This is only meant for building specific fork states in tests: if a
+    /// later slot on this fork already wrote one of the accounts, that
+    /// write keeps shadowing the injected one.
+    pub fn apply_at_slot(&self, tx: &Transaction, slot: Slot) -> Result<(), TransactionError> {
+        let index = self
+            .ancestors
+            .iter()
+            .position(|ancestor| *ancestor == slot)
+            .ok_or(TransactionError::SlotNotInAncestors(slot))?;
+
+        self.apply_with_ancestors(tx, &self.ancestors[..=index], tx.from)
+    }
+
+    fn apply_with_ancestors(
+        &self,
+        tx: &Transaction,
+        ancestors: &[Slot],
+        fee_payer: AccountId,
+    ) -> Result<(), TransactionError> {
+        let Some(processed) = &self.processed else {
+            return self.execute(tx, ancestors, fee_payer);
+        };
+
+        // claim the id before executing so that racing duplicates can't both
+        // pass the check, and give it back if execution fails
+        let id = tx.id();
+        let slot = *ancestors.last().expect("ancestors are never empty");
+        {
+            let mut processed = processed.lock().unwrap();
+            if already_processed(&processed, ancestors, &id) {
+                return Err(TransactionError::AlreadyProcessed);
+            }
+            processed.entry(slot).or_default().insert(id);
+        }
+
+        let result = self.execute(tx, ancestors, fee_payer);
+        if result.is_err() {
+            processed
+                .lock()
+                .unwrap()
+                .get_mut(&slot)
+                .unwrap()
+                .remove(&id);
+        }
+
+        result
+    }
+
+    fn execute(
+        &self,
+        tx: &Transaction,
+        ancestors: &[Slot],
+        fee_payer: AccountId,
+    ) -> Result<(), TransactionError> {
+        if self.is_frozen() {
+            return Err(TransactionError::BankFrozen);
+        }
+
+        let slot = *ancestors.last().expect("ancestors are never empty");
+        let (read_account_ids, mut write_account_ids) = tx.checked_lock_set()?;
+        // the executor only sees the transaction's own accounts, so a separate
+        // fee payer goes on the end
+        let executed = write_account_ids.len();
+        if !write_account_ids.contains(&fee_payer) {
+            write_account_ids.push(fee_payer);
+        }
+        let payer = write_account_ids
+            .iter()
+            .position(|account_id| *account_id == fee_payer)
+            .expect("fee payer is locked");
+        self.check_write_allowed(&write_account_ids)?;
+        let (_read_accounts, mut write_accounts) = self
+            .accounts_db
+            .load_versioned_accounts(&read_account_ids, &write_account_ids)?;
+
+        // what each account starts from instead, if it replaces this slot's
+        // earlier write
+        let mut overwritten: Vec<Option<Account>> = vec![None; write_accounts.len()];
+        match self.conflict_policy {
+            ConflictPolicy::Accumulate => {}
+            ConflictPolicy::Overwrite => {
+                if write_accounts
+                    .iter()
+                    .any(|write_account| write_account.has_update_in(slot))
+                    && !self
+                        .accounts_db
+                        .slot_writes_within(slot, &write_account_ids)?
+                {
+                    return Err(TransactionError::SlotConflict);
+                }
+                let parent_ancestors = &ancestors[..ancestors.len() - 1];
+                for (write_account, overwritten) in write_accounts.iter().zip(&mut overwritten) {
+                    if write_account.has_update_in(slot) {
+                        let parent = write_account.get_account(parent_ancestors);
+                        *overwritten =
+                            Some(parent.unwrap_or(self.accounts_db.default_account()).clone());
+                    }
+                }
+            }
+            ConflictPolicy::Reject => {
+                if write_accounts
+                    .iter()
+                    .any(|write_account| write_account.has_update_in(slot))
+                {
+                    return Err(TransactionError::SlotConflict);
+                }
+            }
+        }
+
+        // run against copies and only load the accounts for writing once
+        // everything has succeeded, so a failure doesn't even create them
+        let mut staged: Vec<Account> = write_accounts
+            .iter()
+            .zip(overwritten)
+            .map(|(write_account, overwritten)| {
+                overwritten.unwrap_or_else(|| {
+                    write_account
+                        .get_account(ancestors)
+                        .unwrap_or(self.accounts_db.default_account())
+                        .clone()
+                })
+            })
+            .collect();
+        #[cfg(feature = "debug-invariants")]
+        let totals_before = balance_totals(&staged);
+        self.charge_fee(tx, &mut staged[payer])?;
+        self.executor
+            .execute(tx, &mut staged[..executed].iter_mut().collect::<Vec<_>>())?;
+        #[cfg(feature = "debug-invariants")]
+        if self.executor.conserves_balance() {
+            let mut expected = totals_before;
+            *expected.entry(NATIVE_TOKEN_ID).or_default() -= u128::from(self.fee_schedule.fee(tx));
+            let totals_after = balance_totals(&staged);
+            assert!(
+                totals_after == expected,
+                "{tx:?} changed the total balances of {write_account_ids:?} from \
+                 {expected:?} (after the fee) to {totals_after:?}"
+            );
+        }
+        for (write_account, staged) in write_accounts.iter_mut().zip(staged) {
+            *write_account.load_account(ancestors)? = staged;
+        }
+        if self.track_finality {
+            self.accounts_db.record_applied(tx.id(), slot);
+        }
+
+        if let Some(sink) = &self.event_sink {
+            let _ = sink.send(BankEvent::Transfer {
+                from: tx.from,
+                to: tx.to,
+                amount: tx.amount,
+                token: tx.token,
+                slot,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Charge `tx`'s fee to the payer, the first of `accounts`, then run the
+    /// executor. Returns the fee charged.
+    fn charge_and_execute(
+        &self,
+        tx: &Transaction,
+        accounts: &mut [&mut Account],
+    ) -> Result<u64, TransactionError> {
+        let fee = self.charge_fee(tx, accounts[0])?;
+        self.executor.execute(tx, accounts)?;
+
+        Ok(fee)
+    }
+
+    /// Debit `tx`'s fee from `payer` and return it.
+    fn charge_fee(&self, tx: &Transaction, payer: &mut Account) -> Result<u64, TransactionError> {
+        let fee = self.fee_schedule.fee(tx);
+        if payer.balance < fee {
+            return Err(TransactionError::InsufficientFunds);
+        }
+        payer.balance -= fee;
+
+        Ok(fee)
+    }
+
+    /// Check whether `tx` would be accepted right now: the bank isn't
+    /// frozen, it names each account once and doesn't write a pinned
+    /// account, it hasn't already landed
+    /// (with dedup on), and the sender covers the fee and amount. Reads
+    /// without locking, so a concurrent write can still change the answer,
+    /// and a custom executor can still reject a transaction that passes.
+    pub fn validate(&self, tx: &Transaction) -> Result<(), TransactionError> {
+        self.check_admissible(tx)?;
+
+        let from = self.get_account(tx.from).unwrap_or_default();
+        let fee = self.fee_schedule.fee(tx);
+        let covered = if tx.token == NATIVE_TOKEN_ID {
+            fee.checked_add(tx.amount)
+                .is_some_and(|total| total <= from.balance)
+        } else {
+            fee <= from.balance && tx.amount <= from.token_balance(tx.token)
+        };
+        if !covered {
+            return Err(TransactionError::InsufficientFunds);
+        }
+
+        Ok(())
+    }
+
+    /// The checks `validate` and `simulate_batch` run before looking at
+    /// balances. Returns `tx`'s write accounts.
+    fn check_admissible(&self, tx: &Transaction) -> Result<Vec<AccountId>, TransactionError> {
+        if self.is_frozen() {
+            return Err(TransactionError::BankFrozen);
+        }
+        let (_, write_account_ids) = tx.checked_lock_set()?;
+        if write_account_ids
+            .iter()
+            .any(|account_id| self.accounts_db.is_pinned(*account_id))
+        {
+            return Err(TransactionError::ImmutableAccount);
+        }
+        self.check_write_allowed(&write_account_ids)?;
+        if let Some(processed) = &self.processed {
+            if already_processed(&processed.lock().unwrap(), &self.ancestors, &tx.id()) {
+                return Err(TransactionError::AlreadyProcessed);
+            }
+        }
+
+        Ok(write_account_ids)
+    }
+
+    /// Run `tx` against a copy of its accounts without writing anything.
+    pub fn simulate(&self, tx: &Transaction) -> Result<SimulationResult, TransactionError> {
+        self.simulate_batch(std::slice::from_ref(tx)).remove(0)
+    }
+
+    /// Run `txs` in order against copies of their accounts, each seeing the
+    /// effects of the earlier successful ones, without writing anything to
+    /// the db. Every transaction gets its own outcome, and goes through the
+    /// same checks as applying it: frozen bank, pinned accounts, the write
+    /// allowlist, dedup (against earlier transactions in the batch too) and
+    /// the conflict policy.
+    pub fn simulate_batch(
+        &self,
+        txs: &[Transaction],
+    ) -> Vec<Result<SimulationResult, TransactionError>> {
+        let parent_ancestors = &self.ancestors[..self.ancestors.len() - 1];
+        // this slot's writes made by earlier transactions in the batch
+        let mut overlay: HashMap<AccountId, Account> = HashMap::new();
+        let mut simulated: HashSet<TransactionId> = HashSet::new();
+
+        txs.iter()
+            .map(|tx| {
+                let write_account_ids = self.check_admissible(tx)?;
+                if self.processed.is_some() && simulated.contains(&tx.id()) {
+                    return Err(TransactionError::AlreadyProcessed);
+                }
+
+                let conflict = write_account_ids.iter().any(|account_id| {
+                    overlay.contains_key(account_id)
+                        || self
+                            .accounts_db
+                            .get_versioned_account(*account_id)
+                            .is_some_and(|versioned_account| {
+                                versioned_account.has_update_in(self.slot)
+                            })
+                });
+                let overwrite = match self.conflict_policy {
+                    ConflictPolicy::Accumulate => false,
+                    ConflictPolicy::Reject if conflict => {
+                        return Err(TransactionError::SlotConflict)
+                    }
+                    ConflictPolicy::Overwrite if conflict => {
+                        let covered = overlay
+                            .keys()
+                            .all(|account_id| write_account_ids.contains(account_id))
+                            && self
+                                .accounts_db
+                                .slot_writes_within(self.slot, &write_account_ids)?;
+                        if !covered {
+                            return Err(TransactionError::SlotConflict);
+                        }
+                        true
+                    }
+                    ConflictPolicy::Reject | ConflictPolicy::Overwrite => false,
+                };
+
+                let mut accounts: Vec<Account> = write_account_ids
+                    .iter()
+                    .map(|account_id| {
+                        let account = if overwrite {
+                            self.accounts_db
+                                .get_versioned_account(*account_id)
+                                .and_then(|versioned_account| {
+                                    versioned_account.get_account(parent_ancestors).cloned()
+                                })
+                        } else {
+                            overlay
+                                .get(account_id)
+                                .cloned()
+                                .or_else(|| self.get_account(*account_id))
+                        };
+                        account.unwrap_or_else(|| self.accounts_db.default_account().clone())
+                    })
+                    .collect();
+                let mut account_refs: Vec<&mut Account> = accounts.iter_mut().collect();
+
+                let fee = self.charge_and_execute(tx, &mut account_refs)?;
+
+                let accounts: Vec<(AccountId, Account)> =
+                    write_account_ids.into_iter().zip(accounts).collect();
+                overlay.extend(accounts.iter().cloned());
+                simulated.insert(tx.id());
+
+                Ok(SimulationResult { fee, accounts })
+            })
+            .collect()
+    }
+
+    /// Move `amount` from `from` to `to`, failing if `from` can't cover it.
+    pub fn transfer(
+        &self,
+        from: AccountId,
+        to: AccountId,
+        amount: u64,
+    ) -> Result<(), TransactionError> {
+        self.with_pair(from, to, &self.ancestors, |from, to| {
+            if from.balance < amount {
+                return Err(TransactionError::InsufficientFunds);
+            }
+            from.balance -= amount;
+            to.balance += amount;
+
+            Ok(())
+        })
+    }
+
+    /// `transfer`, but only if `condition` holds at least `min_balance` on this
+    /// fork. `condition` is read-locked along with the write locks on `from`
+    /// and `to`, so it can't change between the check and the transfer.
+    /// Returns whether the transfer happened.
+    pub fn transfer_if(
+        &self,
+        from: AccountId,
+        to: AccountId,
+        amount: u64,
+        condition: AccountId,
+        min_balance: u64,
+    ) -> Result<bool, TransactionError> {
+        if self.is_frozen() {
+            return Err(TransactionError::BankFrozen);
+        }
+
+        // a condition that's also written is read through the write lock
+        let read_account_ids: &[AccountId] = if condition == from || condition == to {
+            &[]
+        } else {
+            &[condition]
+        };
+        let mut guard = self.lock_accounts(read_account_ids, &[from, to])?;
+        if guard.read(condition).map_or(0, |account| account.balance) < min_balance {
+            return Ok(false);
+        }
+
+        // check before loading anything for writing, so a failure doesn't
+        // create any of the accounts
+        if guard.read(from).map_or(0, |account| account.balance) < amount {
+            return Err(TransactionError::InsufficientFunds);
+        }
+        guard.write(from)?.balance -= amount;
+        guard.write(to)?.balance += amount;
+
+        Ok(true)
+    }
+
+    /// Move as much of `amount` as `from` can cover and return how much moved.
+    /// Fails, moving nothing, if the accounts can't be loaded.
+    pub fn transfer_saturating(
+        &self,
+        from: AccountId,
+        to: AccountId,
+        amount: u64,
+    ) -> Result<u64, TransactionError> {
+        self.with_pair(from, to, &self.ancestors, |from, to| {
+            let moved = amount.min(from.balance);
+            from.balance -= moved;
+            to.balance += moved;
+
+            Ok(moved)
+        })
+    }
+
+    /// Create `new_id` with `lamports` moved from `funder`, owned by `owner` and
+    /// holding `data`. Both accounts are locked together, so either the
+    /// account appears fully formed or nothing changes. Fails if `new_id`
+    /// already exists or `lamports` can't cover rent for `data`.
+    pub fn create_account_full(
+        &self,
+        funder: AccountId,
+        new_id: AccountId,
+        lamports: u64,
+        owner: AccountId,
+        data: Vec<u8>,
+    ) -> Result<(), TransactionError> {
+        if self.is_frozen() {
+            return Err(TransactionError::BankFrozen);
+        }
+        if data.len() > MAX_DATA_LEN {
+            return Err(TransactionError::DataTooLarge);
+        }
+        let account = Account {
+            balance: lamports,
+            owner,
+            data,
+            ..Default::default()
+        };
+        if self.rent.below_minimum(&account) {
+            return Err(TransactionError::InsufficientFunds);
+        }
+
+        let mut guard = self.lock_accounts(&[], &[funder, new_id])?;
+        if guard.read(new_id).is_some() {
+            return Err(TransactionError::AccountAlreadyExists);
+        }
+
+        // check before loading anything for writing, so a failure doesn't
+        // create any of the accounts
+        if guard.read(funder).map_or(0, |account| account.balance) < lamports {
+            return Err(TransactionError::InsufficientFunds);
+        }
+        guard.write(funder)?.balance -= lamports;
+        *guard.write(new_id)? = account;
+
+        Ok(())
+    }
+
+    /// Exchange the native balances of `a` and `b`, locking both once.
+    pub fn swap_balances(&self, a: AccountId, b: AccountId) -> Result<(), TransactionError> {
+        self.with_pair(a, b, &self.ancestors, |a, b| {
+            std::mem::swap(&mut a.balance, &mut b.balance);
+            Ok(())
+        })
+    }
+
+    /// Lock `first` and `second` for writing and hand copies of both to `f`,
+    /// resolved against `ancestors`. The copies are only written back if `f`
+    /// succeeds, so a failure doesn't even create the accounts. They can't be
+    /// the same account.
+    fn with_pair<R>(
+        &self,
+        first: AccountId,
+        second: AccountId,
+        ancestors: &[Slot],
+        f: impl FnOnce(&mut Account, &mut Account) -> Result<R, TransactionError>,
+    ) -> Result<R, TransactionError> {
+        if self.is_frozen() {
+            return Err(TransactionError::BankFrozen);
+        }
+        if first == second {
+            return Err(TransactionError::DuplicateAccount(first));
+        }
+
+        let (_, mut write_accounts) = self
+            .accounts_db
+            .load_versioned_accounts(&[], &[first, second])?;
+
+        let mut staged = write_accounts.iter().map(|write_account| {
+            write_account
+                .get_account(ancestors)
+                .unwrap_or(self.accounts_db.default_account())
+                .clone()
+        });
+        let mut first = staged.next().expect("first is locked");
+        let mut second = staged.next().expect("second is locked");
+        let result = f(&mut first, &mut second)?;
+
+        *write_accounts[0].load_account(ancestors)? = first;
+        *write_accounts[1].load_account(ancestors)? = second;
+
+        Ok(result)
+    }
+
+    /// Debit `from` the sum of `payments` and credit each recipient its
+    /// amount. `from` and every recipient are locked once, together, so
+    /// either every payment lands or none do. A recipient named more than
+    /// once is credited the sum of its amounts, and payments back to `from`
+    /// are credited to it after the debit.
+    pub fn pay_many(
+        &self,
+        from: AccountId,
+        payments: &[(AccountId, u64)],
+    ) -> Result<(), TransactionError> {
+        if self.is_frozen() {
+            return Err(TransactionError::BankFrozen);
+        }
+
+        let total = payments
+            .iter()
+            .try_fold(0u64, |total, (_, amount)| total.checked_add(*amount))
+            .ok_or(TransactionError::InsufficientFunds)?;
+
+        // each account can only be locked once
+        let mut credits: BTreeMap<AccountId, u64> = BTreeMap::new();
+        for (to, amount) in payments {
+            *credits.entry(*to).or_default() += amount;
+        }
+        let refund = credits.remove(&from).unwrap_or_default();
+
+        let write_account_ids: Vec<AccountId> = std::iter::once(from)
+            .chain(credits.keys().copied())
+            .collect();
+        let (_, mut write_accounts) = self
+            .accounts_db
+            .load_versioned_accounts(&[], &write_account_ids)?;
+
+        // check before loading anything for writing, so a failure doesn't
+        // create any of the accounts
+        let balance = write_accounts[0]
+            .get_account(&self.ancestors)
+            .map_or(0, |account| account.balance);
+        if balance < total {
+            return Err(TransactionError::InsufficientFunds);
+        }
+
+        let (from_slice, to_slice) = write_accounts.split_at_mut(1);
+        from_slice[0].load_account(&self.ancestors)?.balance = balance - total + refund;
+
+        for (to, amount) in to_slice.iter_mut().zip(credits.into_values()) {
+            to.load_account(&self.ancestors)?.balance += amount;
+        }
+
+        Ok(())
+    }
+}
+
+/// The cost of reorging from the head `from` to the competing head `to`.
+pub fn reorg_cost(from: &Bank, to: &Bank) -> ReorgCost {
+    let common = from.common_ancestors_len(to);
+    let slots_since_common: Vec<Slot> = from.ancestors[common..]
+        .iter()
+        .chain(&to.ancestors[common..])
+        .copied()
+        .collect();
+
+    let changed: Vec<(u64, u64)> = from
+        .accounts_db
+        .accounts_modified_in_any(&slots_since_common)
+        .into_iter()
+        .filter(|account_id| !from.accounts_db.is_pinned(*account_id))
+        .map(|account_id| (from.get_account(account_id), to.get_account(account_id)))
+        .filter(|(before, after)| before != after)
+        .map(|(before, after)| {
+            let balance = |account: Option<Account>| account.map_or(0, |account| account.balance);
+            (balance(before), balance(after))
+        })
+        .collect();
+
+    ReorgCost {
+        slots_rolled_back: from.ancestors.len() - common,
+        accounts_changed: changed.len(),
+        balance_moved: changed
+            .iter()
+            .map(|(before, after)| before.abs_diff(*after))
+            .sum(),
+    }
+}
+
+/// Panic unless each of `banks` sees `account_id` as whatever its own
+/// ancestor chain last wrote, falling back to the finalized version, so no
+/// bank observes a write made on another fork. The expected value comes from
+/// replaying the fork's writes into a fresh db, where they're the only fork,
+/// and taking the latest one, so it doesn't go through the fork resolution
+/// being checked.
+pub fn assert_forks_isolated(banks: &[&Bank], account_id: AccountId) {
+    for bank in banks {
+        let replayed = AccountsDb::from_state(
+            bank.accounts_db
+                .finalized_account(account_id)
+                .map(|account| (account_id, account))
+                .into_iter()
+                .collect(),
+        );
+        for (_, slot, account) in bank
+            .accounts_db
+            .fork_updates(&bank.ancestors)
+            .into_iter()
+            .filter(|(updated_id, _, _)| *updated_id == account_id)
+        {
+            let (_, mut write_accounts) = replayed
+                .load_versioned_accounts(&[], &[account_id])
+                .expect("nothing else holds the replayed db");
+            write_accounts[0].set_account(account, slot);
+        }
+        let expected = replayed
+            .get_versioned_account(account_id)
+            .and_then(|versioned_account| {
+                versioned_account
+                    .peek_latest()
+                    .map(|(_, account)| account.clone())
+            })
+            .or_else(|| replayed.finalized_account(account_id));
+
+        assert_eq!(
+            bank.get_account(account_id),
+            expected,
+            "bank at slot {} sees account {account_id} from outside its ancestors",
+            bank.slot
+        );
+    }
+}
+
+/// The total of each token's balance, native included, across `accounts`.
+#[cfg(feature = "debug-invariants")]
+fn balance_totals(accounts: &[Account]) -> BTreeMap<TokenId, u128> {
+    let mut totals: BTreeMap<TokenId, u128> = BTreeMap::new();
+    for account in accounts {
+        *totals.entry(NATIVE_TOKEN_ID).or_default() += u128::from(account.balance);
+        for (token, balance) in &account.tokens {
+            *totals.entry(*token).or_default() += u128::from(*balance);
+        }
+    }
+
+    totals
+}
+
+/// Whether transaction `id` landed in any of `ancestors`.
+fn already_processed(
+    processed: &ProcessedTransactions,
+    ancestors: &[Slot],
+    id: &TransactionId,
+) -> bool {
+    ancestors
+        .iter()
+        .any(|ancestor| processed.get(ancestor).is_some_and(|ids| ids.contains(id)))
+}
+
+/// Replay `log` from genesis, one child bank per entry, and return the state
+/// hash of the last bank.
+fn replay(log: &[(Slot, Vec<Transaction>)]) -> Result<[u8; 32], TransactionError> {
+    // every transaction in a block takes effect
+    let mut bank = Bank::genesis_bank().with_conflict_policy(ConflictPolicy::Accumulate);
+    for (slot, txs) in log {
+        bank = bank.new_from_parent(*slot);
+        for tx in txs {
+            bank.apply_with_ancestors(tx, &bank.ancestors, tx.from)?;
+        }
+    }
+
+    Ok(bank.state_hash())
+}
+
+/// Replay `log` twice from separate genesis banks and check both runs end in
+/// the same state.
+pub fn verify_deterministic(log: &[(Slot, Vec<Transaction>)]) -> Result<(), DeterminismError> {
+    let first = replay(log)?;
+    let second = replay(log)?;
+
+    if first != second {
+        return Err(DeterminismError::HashMismatch(first, second));
+    }
+
+    Ok(())
+}
+
+fn main() {
+    println!("Hello, world!");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_account() {
+        let bank = Bank::genesis_bank();
+
+        assert_eq!(bank.slot, 0);
+        assert_eq!(bank.ancestors, vec![0]);
+
+        assert_eq!(
+            bank.get_account(0),
+            Some(Account {
+                balance: GENESIS_SUPPLY,
+                ..Default::default()
+            })
+        );
+        assert_eq!(bank.get_account(1), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        let bank_0 = Bank::genesis_bank();
+
+        let tx = Transaction {
+            from: 0,
+            to: 1,
+            amount: 42,
+            token: NATIVE_TOKEN_ID,
+            nonce: 0,
+            priority: 0,
+        };
+
+        bank_0.apply(&tx);
+
+        assert_eq!(bank_0.get_account(0).unwrap().balance, GENESIS_SUPPLY - 42);
+        assert_eq!(bank_0.get_account(1).unwrap().balance, 42);
+
+        let bank_1 = bank_0.new_from_parent(1);
+
+        assert_eq!(bank_1.get_account(0).unwrap().balance, GENESIS_SUPPLY - 42);
+        assert_eq!(bank_1.get_account(1).unwrap().balance, 42);
+
+        let tx = Transaction {
+            from: 1,
+            to: 0,
+            amount: 10,
+            token: NATIVE_TOKEN_ID,
+            nonce: 0,
+            priority: 0,
+        };
+
+        bank_1.apply(&tx);
+
+        assert_eq!(bank_1.get_account(0).unwrap().balance, GENESIS_SUPPLY - 32);
+        assert_eq!(bank_1.get_account(1).unwrap().balance, 32);
+
+        assert_eq!(bank_0.get_account(0).unwrap().balance, GENESIS_SUPPLY - 42);
+        assert_eq!(bank_0.get_account(1).unwrap().balance, 42);
+
+        // a competing fork
+        let bank_2 = bank_0.new_from_parent(2);
+
+        // 0 is a double-spender :)
+        let tx = Transaction {
+            from: 0,
+            to: 1,
+            amount: 1,
+            token: NATIVE_TOKEN_ID,
+            nonce: 0,
+            priority: 0,
+        };
+
+        bank_2.apply(&tx);
+
+        assert_eq!(bank_2.get_account(0).unwrap().balance, GENESIS_SUPPLY - 43);
+        assert_eq!(bank_2.get_account(1).unwrap().balance, 43);
+
+        assert_eq!(bank_1.get_account(0).unwrap().balance, GENESIS_SUPPLY - 32);
+        assert_eq!(bank_1.get_account(1).unwrap().balance, 32);
+
+        bank_2.finalize();
+
+        assert_eq!(bank_1.get_account(0).unwrap().balance, GENESIS_SUPPLY - 43);
+        assert_eq!(bank_1.get_account(1).unwrap().balance, 43);
+    }
+
+    /// Genesis pays 1 42, then forks: slot 1 pays 10 back, slot 2 pays 1
+    /// another 1. Returns both heads with the transactions each applied.
+    fn competing_forks() -> (Bank, Transaction, Bank, Transaction) {
+        let tx = |from, to, amount| {
+            Transaction::builder()
+                .from(from)
+                .to(to)
+                .amount(amount)
+                .build()
+                .unwrap()
+        };
+        let bank_0 = Bank::genesis_bank().with_finality_tracking();
+        bank_0.apply(&tx(0, 1, 42));
+
+        let (tx_1, tx_2) = (tx(1, 0, 10), tx(0, 1, 1));
+        let bank_1 = bank_0.new_from_parent(1);
+        bank_1.apply(&tx_1);
+        let bank_2 = bank_0.new_from_parent(2);
+        bank_2.apply(&tx_2);
+
+        (bank_1, tx_1, bank_2, tx_2)
+    }
+
+    #[test]
+    fn test_reorg_to() {
+        let (bank_1, tx_1, bank_2, tx_2) = competing_forks();
+
+        let reorg = bank_1.reorg_to(&bank_2);
+        assert_eq!(reorg.bank.ancestors, bank_2.ancestors);
+        assert_eq!(reorg.reverted_transactions, vec![(1, tx_1.id())]);
+        assert_eq!(reorg.applied_transactions, vec![(2, tx_2.id())]);
+        assert_eq!(
+            reorg.reverted,
+            vec![
+                (
+                    0,
+                    1,
+                    Account {
+                        balance: GENESIS_SUPPLY - 32,
+                        ..Default::default()
+                    }
+                ),
+                (
+                    1,
+                    1,
+                    Account {
+                        balance: 32,
+                        ..Default::default()
+                    }
+                ),
+            ]
+        );
+        assert_eq!(
+            reorg.applied,
+            vec![
+                (
+                    0,
+                    2,
+                    Account {
+                        balance: GENESIS_SUPPLY - 43,
+                        ..Default::default()
+                    }
+                ),
+                (
+                    1,
+                    2,
+                    Account {
+                        balance: 43,
+                        ..Default::default()
+                    }
+                ),
+            ]
+        );
+
+        // rooting a fork keeps its transactions findable
+        bank_1.finalize();
+        assert_eq!(
+            bank_1.accounts_db.transactions_applied_in(&[1]),
+            vec![(1, tx_1.id())]
+        );
+    }
+
+    #[test]
+    fn test_reorg_cost() {
+        let (bank_1, _, bank_2, _) = competing_forks();
+
+        // 0 and 1 differ by 11 each way
+        assert_eq!(
+            reorg_cost(&bank_1, &bank_2),
+            ReorgCost {
+                slots_rolled_back: 1,
+                accounts_changed: 2,
+                balance_moved: 22,
+            }
+        );
+        assert_eq!(
+            reorg_cost(&bank_1, &bank_1),
+            ReorgCost {
+                slots_rolled_back: 0,
+                accounts_changed: 0,
+                balance_moved: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_apply_at_slot() {
+        let bank_0 = Bank::genesis_bank();
+        let bank_1 = bank_0.new_from_parent(1);
+        let bank_2 = bank_1.new_from_parent(2);
+
+        let tx = Transaction {
+            from: 0,
+            to: 1,
+            amount: 42,
+            token: NATIVE_TOKEN_ID,
+            nonce: 0,
+            priority: 0,
+        };
+
+        assert_eq!(
+            bank_2.apply_at_slot(&tx, 3),
+            Err(TransactionError::SlotNotInAncestors(3))
+        );
+
+        bank_2.apply_at_slot(&tx, 1).expect("apply at slot");
+
+        assert_eq!(bank_0.get_account(1), None);
+        assert_eq!(bank_1.get_account(1).unwrap().balance, 42);
+        assert_eq!(bank_2.get_account(1).unwrap().balance, 42);
+
+        // a sibling of bank_1 doesn't see the injected write
+        let bank_3 = bank_0.new_from_parent(3);
+        assert_eq!(bank_3.get_account(1), None);
+        assert_eq!(bank_2.accounts_db.verify_all(), Ok(()));
+    }
+
+    #[test]
+    fn test_reset_account() {
+        let bank_0 = Bank::genesis_bank();
+        bank_0.transfer(0, 1, 10).expect("transfer");
+
+        let bank_1 = bank_0.new_from_parent(1);
+        bank_1.transfer(1, 2, 3).expect("transfer");
+        assert_eq!(bank_1.get_account(1).unwrap().balance, 7);
+
+        bank_1.reset_account(1).expect("reset");
+
+        assert_eq!(bank_1.get_account(1).unwrap().balance, 10);
+        assert_eq!(bank_0.get_account(1).unwrap().balance, 10);
+        // only the account that was reset reverts
+        assert_eq!(bank_1.get_account(2).unwrap().balance, 3);
+
+        // there's nothing of bank_1's left to discard, and the parent's
+        // write survives
+        bank_1.reset_account(1).expect("reset");
+        assert_eq!(bank_1.get_account(1).unwrap().balance, 10);
+
+        // discarding the write that created an account uncounts it
+        let account_count = bank_1.account_count();
+        bank_1.transfer(2, 7, 1).expect("transfer");
+        assert_eq!(bank_1.account_count(), account_count + 1);
+        bank_1.reset_account(7).expect("reset");
+        assert_eq!(bank_1.get_account(7), None);
+        assert_eq!(bank_1.account_count(), account_count);
+    }
+
+    #[test]
+    fn test_state_hash_with() {
+        // sums every byte it sees into the first byte of the digest
+        #[derive(Default)]
+        struct SummingHasher(u8);
+
+        impl StateHasher for SummingHasher {
+            fn update(&mut self, bytes: &[u8]) {
+                for byte in bytes {
+                    self.0 = self.0.wrapping_add(*byte);
+                }
+            }
+
+            fn finalize(self) -> [u8; 32] {
+                let mut digest = [0; 32];
+                digest[0] = self.0;
+                digest
+            }
+        }
+
+        let bank = Bank::genesis_bank();
+        // GENESIS_SUPPLY is 0x0f4240
+        assert_eq!(
+            bank.state_hash_with::<SummingHasher>()[0],
+            0x0f + 0x42 + 0x40
+        );
+
+        bank.transfer(0, 1, 1).expect("transfer");
+        // account 1 adds 1 for its id and 1 for its balance, and account 0's
+        // low balance byte drops by one
+        assert_eq!(
+            bank.state_hash_with::<SummingHasher>()[0],
+            0x0f + 0x42 + 0x40 + 1
+        );
+
+        assert_ne!(bank.state_hash(), Bank::genesis_bank().state_hash());
+    }
+
+    #[test]
+    fn test_pinned_account() {
+        let bank = Bank::genesis_bank();
+        bank.transfer(0, 1, 10).expect("transfer");
+        bank.accounts_db.pin(1);
+
+        assert_eq!(
+            bank.transfer(0, 1, 5),
+            Err(TransactionError::ImmutableAccount)
+        );
+        assert_eq!(
+            bank.transfer(1, 0, 5),
+            Err(TransactionError::ImmutableAccount)
+        );
+        assert_eq!(bank.get_account(0).unwrap().balance, GENESIS_SUPPLY - 10);
+        assert_eq!(bank.get_account(1).unwrap().balance, 10);
+
+        bank.accounts_db
+            .set_sysvar(
+                1,
+                Account {
+                    balance: 99,
+                    ..Default::default()
+                },
+                &bank.ancestors,
+            )
+            .expect("set sysvar");
+        assert_eq!(bank.get_account(1).unwrap().balance, 99);
+    }
+
+    #[test]
+    fn test_clock() {
+        let bank_0 = Bank::genesis_bank();
+        assert_eq!(bank_0.clock(), 0);
+
+        let bank_1 = bank_0.new_from_parent(1);
+        let bank_3 = bank_1.advance_empty(3);
+        // created after its higher-slot cousin
+        let bank_2 = bank_0.new_from_parent(2);
+
+        assert_eq!(bank_0.clock(), 0);
+        assert_eq!(bank_1.clock(), 1);
+        assert_eq!(bank_3.clock(), 3);
+        assert_eq!(bank_2.clock(), 2);
+        assert_eq!(bank_0.accounts_db.verify_all(), Ok(()));
+        // it's not part of the state
+        assert_eq!(bank_1.state_hash(), bank_0.state_hash());
+        assert_eq!(bank_1.account_count(), bank_0.account_count());
+        assert_eq!(bank_1.dirty_accounts(), vec![]);
+
+        // rooting slot 2 promotes its clock despite slot 3 being written first
+        bank_2.finalize();
+        assert_eq!(bank_2.clock(), 2);
+        assert_eq!(bank_3.clock(), 3);
+
+        assert_eq!(
+            bank_3.transfer(0, CLOCK_ID, 1),
+            Err(TransactionError::ImmutableAccount)
+        );
+    }
+
+    #[test]
+    fn test_account_count() {
+        let bank_0 = Bank::genesis_bank();
+        assert_eq!(bank_0.account_count(), 1);
+
+        // the clock each child bank writes isn't counted
+        let bank_1 = bank_0.new_from_parent(1);
+        let bank_2 = bank_0.new_from_parent(2);
+        assert_eq!(bank_1.account_count(), 1);
+        assert_eq!(bank_2.account_count(), 1);
+
+        bank_1.pay_many(0, &[(1, 1), (2, 1)]).expect("pay_many");
+        bank_1.transfer(1, 2, 1).expect("transfer");
+
+        assert_eq!(bank_1.account_count(), 3);
+        assert_eq!(bank_2.account_count(), 1);
+        assert_eq!(bank_0.account_count(), 1);
+        assert_eq!(
+            bank_1.account_count() as usize,
+            bank_1.accounts_iter().count()
+        );
+
+        bank_1.finalize();
+        assert_eq!(bank_1.account_count(), 3);
+        assert_eq!(bank_1.new_from_parent(3).account_count(), 3);
+    }
+
+    #[test]
+    fn test_try_merge() {
+        let bank_0 = Bank::genesis_bank();
+        bank_0
+            .pay_many(0, &[(1, 10), (2, 10), (3, 10), (4, 10)])
+            .expect("pay_many");
+
+        let bank_1 = bank_0.new_from_parent(1);
+        let bank_2 = bank_0.new_from_parent(2);
+        bank_1.transfer(1, 2, 5).expect("transfer");
+        bank_2.transfer(3, 4, 7).expect("transfer");
+
+        bank_1.try_merge(&bank_2).expect("merge");
+
+        assert_eq!(bank_1.get_account(1).unwrap().balance, 5);
+        assert_eq!(bank_1.get_account(2).unwrap().balance, 15);
+        assert_eq!(bank_1.get_account(3).unwrap().balance, 3);
+        assert_eq!(bank_1.get_account(4).unwrap().balance, 17);
+        assert_eq!(bank_1.clock(), 1);
+        // the other fork is left alone
+        assert_eq!(bank_2.get_account(1).unwrap().balance, 10);
+
+        let bank_3 = bank_0.new_from_parent(3);
+        bank_3.transfer(1, 0, 1).expect("transfer");
+
+        assert_eq!(
+            bank_3.try_merge(&bank_1),
+            Err(MergeError::Conflict(vec![1]))
+        );
+        assert_eq!(bank_3.get_account(2).unwrap().balance, 10);
+    }
+
+    #[test]
+    fn test_apply_across_threads() {
+        let bank = Arc::new(Bank::genesis_bank().with_conflict_policy(ConflictPolicy::Accumulate));
+        let senders: Vec<AccountId> = (1..=4).map(|i| i * 10).collect();
+        bank.pay_many(0, &senders.iter().map(|id| (*id, 100)).collect::<Vec<_>>())
+            .expect("pay_many");
+
+        let workers: Vec<_> = senders
+            .iter()
+            .map(|from| {
+                let bank = bank.clone();
+                let from = *from;
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        bank.apply(&Transaction {
+                            from,
+                            to: from + 1,
+                            amount: 2,
+                            token: NATIVE_TOKEN_ID,
+                            nonce: 0,
+                            priority: 0,
+                        });
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        for from in senders {
+            assert_eq!(bank.get_account(from).unwrap().balance, 0);
+            assert_eq!(bank.get_account(from + 1).unwrap().balance, 100);
+        }
+    }
+
+    #[test]
+    fn test_custom_executor() {
+        struct MintExecutor;
+
+        impl Executor for MintExecutor {
+            fn execute(
+                &self,
+                tx: &Transaction,
+                accounts: &mut [&mut Account],
+            ) -> Result<(), TransactionError> {
+                accounts[1].balance += tx.amount;
+
+                Ok(())
+            }
+        }
+
+        let bank = Bank::genesis_bank().with_executor(Arc::new(MintExecutor));
+        bank.apply(&Transaction {
+            from: 0,
+            to: 1,
+            amount: 500,
+            token: NATIVE_TOKEN_ID,
+            nonce: 0,
+            priority: 0,
+        });
+
+        let supply: u64 = bank
+            .accounts_iter()
+            .map(|(_, account)| account.balance)
+            .sum();
+        assert_eq!(supply, GENESIS_SUPPLY + 500);
+        assert_eq!(bank.get_account(0).unwrap().balance, GENESIS_SUPPLY);
+
+        // children keep minting
+        let child = bank.new_from_parent(1);
+        child.apply(&Transaction {
+            from: 0,
+            to: 1,
+            amount: 1,
+            token: NATIVE_TOKEN_ID,
+            nonce: 0,
+            priority: 0,
+        });
+        assert_eq!(child.get_account(1).unwrap().balance, 501);
+    }
+
+    #[test]
+    fn test_transfer() {
+        let bank = Bank::genesis_bank();
+
+        bank.transfer(0, 1, 100).expect("transfer");
+        let account_count = bank.account_count();
+        assert_eq!(
+            bank.transfer(1, 2, 101),
+            Err(TransactionError::InsufficientFunds)
+        );
+
+        // a failed transfer doesn't create the recipient
+        assert_eq!(bank.get_account(1).unwrap().balance, 100);
+        assert_eq!(bank.get_account(2), None);
+        assert_eq!(bank.account_count(), account_count);
+
+        assert_eq!(
+            bank.transfer(1, 1, 10),
+            Err(TransactionError::DuplicateAccount(1))
+        );
+    }
+
+    #[test]
+    fn test_apply_overspend() {
+        let bank = Bank::genesis_bank().new_from_parent(1);
+        let tx = Transaction::builder()
+            .from(0)
+            .to(1)
+            .amount(GENESIS_SUPPLY + 1)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            bank.apply_at_slot(&tx, 1),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(bank.get_account(0).unwrap().balance, GENESIS_SUPPLY);
+    }
+
+    #[test]
+    fn test_transfer_saturating() {
+        let bank = Bank::genesis_bank();
+        bank.transfer(0, 1, 100).expect("transfer");
+
+        assert_eq!(bank.transfer_saturating(1, 2, 150), Ok(100));
+        assert_eq!(bank.get_account(1).unwrap().balance, 0);
+        assert_eq!(bank.get_account(2).unwrap().balance, 100);
+
+        assert_eq!(bank.transfer_saturating(2, 1, 40), Ok(40));
+        assert_eq!(bank.get_account(1).unwrap().balance, 40);
+        assert_eq!(bank.get_account(2).unwrap().balance, 60);
+
+        // errors come back instead of panicking
+        assert_eq!(
+            bank.transfer_saturating(1, 1, 10),
+            Err(TransactionError::DuplicateAccount(1))
+        );
+        bank.freeze();
+        assert_eq!(
+            bank.transfer_saturating(2, 1, 10),
+            Err(TransactionError::BankFrozen)
+        );
+        assert_eq!(bank.get_account(1).unwrap().balance, 40);
+    }
+
+    #[test]
+    fn test_total_balance_by_owner() {
+        let bank_0 = Bank::genesis_bank();
+        bank_0
+            .pay_many(0, &[(1, 10), (2, 20), (3, 30)])
+            .expect("pay_many");
+
+        let bank_1 = bank_0.new_from_parent(1);
+        {
+            let (_, mut write_accounts) = bank_1
+                .accounts_db
+                .load_versioned_accounts(&[], &[1, 3])
+                .expect("load accounts");
+            for write_account in write_accounts.iter_mut() {
+                write_account.load_account(&bank_1.ancestors).unwrap().owner = 7;
+            }
+        }
+
+        assert_eq!(bank_1.total_balance_by_owner(7), 40);
+        assert_eq!(bank_1.total_balance_by_owner(0), GENESIS_SUPPLY - 40);
+        assert_eq!(bank_0.total_balance_by_owner(7), 0);
+    }
+
+    #[test]
+    fn test_transaction_builder() {
+        let tx = Transaction::builder()
+            .from(0)
+            .to(1)
+            .amount(42)
+            .nonce(7)
+            .build()
+            .expect("build");
+
+        assert_eq!(tx.from, 0);
+        assert_eq!(tx.to, 1);
+        assert_eq!(tx.amount, 42);
+        assert_eq!(tx.nonce, 7);
+
+        assert!(matches!(
+            Transaction::builder().from(0).amount(42).build(),
+            Err(BuildError::MissingField("to"))
+        ));
+    }
+
+    #[test]
+    fn test_advance_empty() {
+        let bank_0 = Bank::genesis_bank();
+        let bank_10 = bank_0.advance_empty(10);
+
+        assert_eq!(bank_10.slot, 10);
+        assert_eq!(bank_10.ancestors, vec![0, 10]);
+        assert_eq!(
+            bank_10.get_account(0),
+            Some(Account {
+                balance: GENESIS_SUPPLY,
+                ..Default::default()
+            })
+        );
+
+        bank_10.apply(&Transaction {
+            from: 0,
+            to: 1,
+            amount: 5,
+            token: NATIVE_TOKEN_ID,
+            nonce: 0,
+            priority: 0,
+        });
+
+        assert_eq!(bank_10.get_account(1).unwrap().balance, 5);
+        assert_eq!(bank_0.get_account(1), None);
+    }
+
+    #[test]
+    fn test_pay_many() {
+        let bank_0 = Bank::genesis_bank();
+
+        bank_0
+            .pay_many(0, &[(1, 10), (2, 20), (3, 30)])
+            .expect("pay_many");
+
+        assert_eq!(bank_0.get_account(0).unwrap().balance, GENESIS_SUPPLY - 60);
+        assert_eq!(bank_0.get_account(1).unwrap().balance, 10);
+        assert_eq!(bank_0.get_account(2).unwrap().balance, 20);
+        assert_eq!(bank_0.get_account(3).unwrap().balance, 30);
+
+        let bank_1 = bank_0.new_from_parent(1);
+
+        // 1 can't cover 5 + 6
+        assert_eq!(
+            bank_1.pay_many(1, &[(2, 5), (3, 6)]),
+            Err(TransactionError::InsufficientFunds)
+        );
+
+        assert_eq!(bank_1.get_account(1).unwrap().balance, 10);
+        assert_eq!(bank_1.get_account(2).unwrap().balance, 20);
+        assert_eq!(bank_1.get_account(3).unwrap().balance, 30);
+
+        // a failed payment creates neither side
+        let account_count = bank_1.account_count();
+        assert_eq!(
+            bank_1.pay_many(4, &[(5, 1)]),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(bank_1.get_account(4), None);
+        assert_eq!(bank_1.get_account(5), None);
+        assert_eq!(bank_1.account_count(), account_count);
+
+        // repeated recipients are merged, and 1 still has to cover the 4 it
+        // pays itself before getting it back
+        assert_eq!(
+            bank_1.pay_many(1, &[(2, 3), (1, 4), (2, 4)]),
+            Err(TransactionError::InsufficientFunds)
+        );
+        bank_1.pay_many(1, &[(2, 3), (1, 4), (2, 2)]).unwrap();
+        assert_eq!(bank_1.get_account(1).unwrap().balance, 5);
+        assert_eq!(bank_1.get_account(2).unwrap().balance, 25);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_benchmark_pay_many() {
+        const RECIPIENTS: u64 = 10_000;
+
+        let payments: Vec<(AccountId, u64)> = (1..=RECIPIENTS).map(|to| (to, 1)).collect();
+
+        // each run gets a fresh db, so neither pays for the other's accounts
+        let bank = Bank::genesis_bank();
+        let start = std::time::Instant::now();
+        for (to, amount) in &payments {
+            bank.transfer(0, *to, *amount).expect("transfer");
+        }
+        println!(
+            "transfer x{RECIPIENTS} ({} lock acquisitions) elapsed millis: {}",
+            bank.accounts_db.lock_acquisitions(),
+            start.elapsed().as_millis()
+        );
+        assert_eq!(
+            bank.get_account(0).unwrap().balance,
+            GENESIS_SUPPLY - RECIPIENTS
+        );
+
+        let bank = Bank::genesis_bank();
+        let start = std::time::Instant::now();
+        bank.pay_many(0, &payments).expect("pay_many");
+        println!(
+            "pay_many ({} lock acquisitions) elapsed millis: {}",
+            bank.accounts_db.lock_acquisitions(),
+            start.elapsed().as_millis()
+        );
+        assert_eq!(
+            bank.get_account(0).unwrap().balance,
+            GENESIS_SUPPLY - RECIPIENTS
+        );
+    }
+
+    //#[test]
+    //fn test_benchmark() {
+    //    let bank = Bank::genesis_bank();
+
+    //    let tx = Transaction {
+    //        from: 0,
+    //        to: 1,
+    //        amount: 1,
+    //        nonce: 0,
+    //        priority: 0,
+    //    };
+
+    //    let mut total = 0;
+
+    //    let start = std::time::Instant::now();
+
+    //    for _ in 0..1_000_000 {
+    //        bank.apply(&tx);
+    //    }
+
+    //    println!("elapsed millis: {}", start.elapsed().as_millis());
+    //}
+
+    #[test]
+    fn test_verify_deterministic() {
+        let tx = |from, to, amount| Transaction {
+            from,
+            to,
+            amount,
+            token: NATIVE_TOKEN_ID,
+            nonce: 0,
+            priority: 0,
+        };
+        let log = vec![
+            (1, vec![tx(0, 1, 100), tx(0, 2, 50)]),
+            (2, vec![]),
+            (4, vec![tx(1, 2, 30), tx(2, 3, 10)]),
+        ];
+
+        assert_eq!(verify_deterministic(&log), Ok(()));
+        assert_eq!(verify_deterministic(&[]), Ok(()));
+    }
+
+    #[test]
+    fn test_estimate_fee() {
+        let bank_0 = Bank::genesis_bank().with_fee_schedule(FeeSchedule {
+            base_fee: 5,
+            fee_per_priority: 2,
+        });
+        let bank_1 = bank_0.new_from_parent(1);
+
+        let tx = Transaction {
+            from: 0,
+            to: 1,
+            amount: 100,
+            token: NATIVE_TOKEN_ID,
+            nonce: 0,
+            priority: 3,
+        };
+        let fee = bank_1.estimate_fee(&tx);
+        assert_eq!(fee, 11);
+        // estimating doesn't touch any account
+        assert_eq!(bank_1.get_account(0).unwrap().balance, GENESIS_SUPPLY);
+
+        bank_1.apply(&tx);
+
+        assert_eq!(
+            bank_1.get_account(0).unwrap().balance,
+            GENESIS_SUPPLY - 100 - fee
+        );
+        assert_eq!(bank_1.get_account(1).unwrap().balance, 100);
+    }
+
+    #[test]
+    fn test_transfer_token() {
+        const TOKEN_ID: TokenId = 7;
+
+        let bank_0 = Bank::genesis_bank().with_conflict_policy(ConflictPolicy::Accumulate);
+        let bank_1 = bank_0.new_from_parent(1);
+        bank_1.transfer(0, 1, 10).unwrap();
+        {
+            let (_, mut write_accounts) = bank_1
+                .accounts_db
+                .load_versioned_accounts(&[], &[1])
+                .unwrap();
+            *write_accounts[0]
+                .load_account(&bank_1.ancestors)
+                .unwrap()
+                .token_balance_mut(TOKEN_ID) = 50;
+        }
+
+        let tx = Transaction::builder()
+            .from(1)
+            .to(2)
+            .amount(20)
+            .token(TOKEN_ID)
+            .build()
+            .unwrap();
+        bank_1.apply(&tx);
+
+        let from = bank_1.get_account(1).unwrap();
+        let to = bank_1.get_account(2).unwrap();
+        assert_eq!(from.token_balance(TOKEN_ID), 30);
+        assert_eq!(to.token_balance(TOKEN_ID), 20);
+        assert_eq!(from.balance, 10);
+        assert_eq!(to.token_balance(NATIVE_TOKEN_ID), 0);
+        assert_eq!(bank_0.get_account(1), None);
+    }
+
+    #[test]
+    fn test_transfer_token_leaves_no_empty_entries() {
+        const TOKEN_ID: TokenId = 7;
+
+        let bank = |token_balance| {
+            let bank = Bank::genesis_bank().with_conflict_policy(ConflictPolicy::Accumulate);
+            bank.pay_many(0, &[(1, 10), (2, 10)]).unwrap();
+            let (_, mut write_accounts) =
+                bank.accounts_db.load_versioned_accounts(&[], &[1]).unwrap();
+            *write_accounts[0]
+                .load_account(&bank.ancestors)
+                .unwrap()
+                .token_balance_mut(TOKEN_ID) = token_balance;
+            drop(write_accounts);
+
+            bank
+        };
+        let tx = |from, to, amount| {
+            Transaction::builder()
+                .from(from)
+                .to(to)
+                .amount(amount)
+                .token(TOKEN_ID)
+                .build()
+                .unwrap()
+        };
+
+        // moving nothing, and moving everything there and back again
+        let expected = bank(5);
+        let moved = bank(5);
+        moved.apply(&tx(2, 1, 0));
+        moved.apply(&tx(1, 2, 5));
+        moved.apply(&tx(2, 1, 5));
+
+        assert_eq!(moved.get_account(2).unwrap().tokens, BTreeMap::new());
+        assert_eq!(moved.state_hash(), expected.state_hash());
+    }
+
+    #[test]
+    fn test_persist_if_frozen() {
+        let dir = std::env::temp_dir().join(format!("smolchain-persist-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let bank_0 = Bank::genesis_bank();
+        let bank_1 = bank_0.new_from_parent(1);
+        bank_1.transfer(0, 1, 10).unwrap();
+        // a sibling fork that's still inflight once bank_1 is rooted
+        let bank_2 = bank_0.new_from_parent(2);
+        bank_2.transfer(0, 2, 20).unwrap();
+        bank_1.finalize();
+
+        // not frozen
+        assert_eq!(bank_1.persist_if_frozen(&dir).unwrap(), None);
+        let hash = bank_1.freeze();
+        assert_eq!(bank_1.transfer(0, 1, 10), Err(TransactionError::BankFrozen));
+        // frozen, but no longer the root
+        bank_0.freeze();
+        assert_eq!(bank_0.persist_if_frozen(&dir).unwrap(), None);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        let path = bank_1.persist_if_frozen(&dir).unwrap().unwrap();
+        let hash: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+        assert_eq!(path, dir.join(format!("1-{hash}.snapshot")));
+        let restored = AccountsDb::load_snapshot(&path).unwrap();
+        assert!(restored.finalized_state_eq(&bank_1.accounts_db));
+        // the sibling's write isn't in the file
+        assert!(bank_1.accounts_db.contains(2));
+        assert!(!restored.contains(2));
+        assert_eq!(restored.total_inflight_entries(), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dirty_accounts() {
+        let bank_0 = Bank::genesis_bank();
+        assert_eq!(bank_0.dirty_accounts(), vec![]);
+
+        bank_0.transfer(0, 1, 10).unwrap();
+        assert_eq!(bank_0.dirty_accounts(), vec![0, 1]);
+
+        let bank_1 = bank_0.new_from_parent(1);
+        bank_1.transfer(1, 2, 5).unwrap();
+        assert_eq!(bank_1.dirty_accounts(), vec![0, 1, 2]);
+        // a sibling doesn't see bank_1's writes
+        let bank_2 = bank_0.new_from_parent(2);
+        assert_eq!(bank_2.dirty_accounts(), vec![0, 1]);
+
+        bank_1.finalize();
+        assert_eq!(bank_1.dirty_accounts(), vec![]);
+    }
+
+    #[test]
+    fn test_apply_and_hash() {
+        let bank = Bank::genesis_bank()
+            .with_conflict_policy(ConflictPolicy::Accumulate)
+            .new_from_parent(1);
+        let tx = Transaction::builder()
+            .from(0)
+            .to(1)
+            .amount(10)
+            .build()
+            .unwrap();
+
+        let mut hashes = vec![bank.state_hash()];
+        for _ in 0..3 {
+            let hash = bank.apply_and_hash(&tx).unwrap();
+            assert_eq!(hash, bank.state_hash());
+            assert!(!hashes.contains(&hash));
+            hashes.push(hash);
+        }
+
+        bank.freeze();
+        assert_eq!(bank.apply_and_hash(&tx), Err(TransactionError::BankFrozen));
+    }
+
+    #[test]
+    fn test_balance_at_ancestor_index() {
+        let bank_0 = Bank::genesis_bank();
+        let bank_1 = bank_0.new_from_parent(1);
+        bank_1.transfer(0, 1, 10).unwrap();
+        let bank_2 = bank_1.new_from_parent(2);
+        let bank_4 = bank_2.new_from_parent(4);
+        bank_4.transfer(0, 1, 5).unwrap();
+
+        assert_eq!(bank_4.balance_at_ancestor_index(1, 0), None);
+        assert_eq!(bank_4.balance_at_ancestor_index(1, 1), Some(10));
+        assert_eq!(bank_4.balance_at_ancestor_index(1, 2), Some(10));
+        assert_eq!(bank_4.balance_at_ancestor_index(1, 3), Some(15));
+        assert_eq!(bank_4.balance_at_ancestor_index(0, 0), Some(GENESIS_SUPPLY));
+        assert_eq!(bank_4.balance_at_ancestor_index(0, 4), None);
+
+        bank_2.finalize();
+        assert_eq!(bank_4.balance_at_ancestor_index(1, 0), Some(10));
+        assert_eq!(bank_4.balance_at_ancestor_index(1, 3), Some(15));
+    }
+
+    #[test]
+    fn test_parent_slot() {
+        let bank_0 = Bank::genesis_bank();
+        let bank_1 = bank_0.new_from_parent(1);
+        let bank_5 = bank_1.advance_empty(5);
+
+        assert_eq!(bank_0.parent_slot(), None);
+        assert_eq!(bank_1.parent_slot(), Some(0));
+        assert_eq!(bank_5.parent_slot(), Some(1));
+    }
+
+    #[test]
+    fn test_dedup() {
+        let bank_0 = Bank::genesis_bank().with_dedup();
+        let bank_1 = bank_0.new_from_parent(1);
+        let tx = Transaction::builder()
+            .from(0)
+            .to(1)
+            .amount(10)
+            .build()
+            .unwrap();
+
+        bank_1.apply_at_slot(&tx, 1).unwrap();
+        assert_eq!(
+            bank_1.apply_at_slot(&tx, 1),
+            Err(TransactionError::AlreadyProcessed)
+        );
+        // a descendant sees it too
+        let bank_2 = bank_1.new_from_parent(2);
+        assert_eq!(
+            bank_2.apply_and_hash(&tx),
+            Err(TransactionError::AlreadyProcessed)
+        );
+        assert_eq!(bank_2.get_account(1).unwrap().balance, 10);
+
+        // a sibling fork hasn't seen it
+        let bank_3 = bank_0.new_from_parent(3);
+        bank_3.apply(&tx);
+        assert_eq!(bank_3.get_account(1).unwrap().balance, 10);
+
+        // a different nonce is a different transaction
+        let tx = Transaction::builder()
+            .from(0)
+            .to(1)
+            .amount(10)
+            .nonce(1)
+            .build()
+            .unwrap();
+        bank_2.apply(&tx);
+        assert_eq!(bank_2.get_account(1).unwrap().balance, 20);
+    }
+
+    #[test]
+    fn test_lock_set() {
+        let tx = Transaction::builder()
+            .from(3)
+            .to(7)
+            .amount(1)
+            .build()
+            .unwrap();
+
+        assert_eq!(tx.lock_set(), (vec![], vec![3, 7]));
+    }
+
+    #[test]
+    fn test_event_sink() {
+        let (sink, events) = std::sync::mpsc::channel();
+        let bank_0 = Bank::genesis_bank()
+            .with_conflict_policy(ConflictPolicy::Accumulate)
+            .with_event_sink(sink);
+        let bank_1 = bank_0.new_from_parent(1);
+        let tx = |from, to, amount| {
+            Transaction::builder()
+                .from(from)
+                .to(to)
+                .amount(amount)
+                .build()
+        };
+
+        bank_1.apply(&tx(0, 1, 10).unwrap());
+        // failures aren't reported
+        bank_1.apply_at_slot(&tx(1, 2, 4).unwrap(), 5).unwrap_err();
+        bank_1.apply(&tx(1, 2, 4).unwrap());
+        drop((bank_0, bank_1));
+
+        assert_eq!(
+            events.iter().collect::<Vec<_>>(),
+            vec![
+                BankEvent::Transfer {
+                    from: 0,
+                    to: 1,
+                    amount: 10,
+                    token: NATIVE_TOKEN_ID,
+                    slot: 1,
+                },
+                BankEvent::Transfer {
+                    from: 1,
+                    to: 2,
+                    amount: 4,
+                    token: NATIVE_TOKEN_ID,
+                    slot: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_swap_balances() {
+        let bank_0 = Bank::genesis_bank();
+        bank_0.transfer(0, 1, 10).unwrap();
+        let bank_1 = bank_0.new_from_parent(1);
+
+        bank_1.swap_balances(0, 1).unwrap();
+
+        assert_eq!(bank_1.get_account(0).unwrap().balance, 10);
+        assert_eq!(bank_1.get_account(1).unwrap().balance, GENESIS_SUPPLY - 10);
+        assert_eq!(bank_0.get_account(0).unwrap().balance, GENESIS_SUPPLY - 10);
+        assert_eq!(
+            bank_1.swap_balances(0, CLOCK_ID),
+            Err(TransactionError::ImmutableAccount)
+        );
+    }
+
+    #[test]
+    fn test_create_account_full() {
+        let bank = Bank::genesis_bank().with_rent(Rent { per_byte: 10 });
+
+        bank.create_account_full(0, 1, 50, 7, vec![1, 2, 3])
+            .unwrap();
+        assert_eq!(
+            bank.get_account(1),
+            Some(Account {
+                balance: 50,
+                owner: 7,
+                data: vec![1, 2, 3],
+                ..Default::default()
+            })
+        );
+        assert_eq!(bank.get_account(0).unwrap().balance, GENESIS_SUPPLY - 50);
+
+        assert_eq!(
+            bank.create_account_full(0, 1, 50, 7, vec![]),
+            Err(TransactionError::AccountAlreadyExists)
+        );
+        // 20 can't cover rent for 3 bytes, and nothing is debited
+        assert_eq!(
+            bank.create_account_full(0, 2, 20, 7, vec![0; 3]),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(bank.get_account(2), None);
+        assert_eq!(bank.get_account(0).unwrap().balance, GENESIS_SUPPLY - 50);
+
+        // a funder that can't cover the lamports creates nothing
+        let account_count = bank.account_count();
+        assert_eq!(
+            bank.create_account_full(3, 4, 50, 7, vec![]),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(bank.get_account(3), None);
+        assert_eq!(bank.get_account(4), None);
+        assert_eq!(bank.account_count(), account_count);
+    }
+
+    #[test]
+    fn test_finalize_through_slot() {
+        let bank_0 = Bank::genesis_bank();
+        let bank_1 = bank_0.new_from_parent(1);
+        bank_1.transfer(0, 1, 10).unwrap();
+        let bank_2 = bank_1.new_from_parent(2);
+        bank_2.transfer(0, 2, 20).unwrap();
+        let bank_3 = bank_2.new_from_parent(3);
+        bank_3.transfer(0, 3, 30).unwrap();
+
+        bank_3.finalize_through_slot(1).unwrap();
+
+        assert_eq!(bank_3.accounts_db.finalized_slot(), 1);
+        assert_eq!(bank_3.accounts_db.accounts_modified_in(1), vec![]);
+        assert_eq!(
+            bank_3.accounts_db.accounts_modified_in_any(&[2, 3]),
+            vec![0, 2, 3, CLOCK_ID]
+        );
+        assert_eq!(bank_3.get_account(3).unwrap().balance, 30);
+        assert_eq!(
+            bank_3.finalize_through_slot(4),
+            Err(TransactionError::SlotNotInAncestors(4))
+        );
+
+        bank_3.finalize_through_slot(2).unwrap();
+        assert_eq!(bank_3.accounts_db.finalized_slot(), 2);
+        assert_eq!(
+            bank_3.accounts_db.accounts_modified_in(3),
+            vec![0, 3, CLOCK_ID]
+        );
+    }
+
+    #[test]
+    fn test_finality_latency() {
+        let tx = |amount| {
+            Transaction::builder()
+                .from(0)
+                .to(1)
+                .amount(amount)
+                .build()
+                .unwrap()
+        };
+        let bank_0 = Bank::genesis_bank().with_finality_tracking();
+        let bank_1 = bank_0.new_from_parent(1);
+        bank_1.apply(&tx(1));
+        let bank_2 = bank_1.new_from_parent(2);
+        bank_2.apply(&tx(2));
+        let bank_3 = bank_2.new_from_parent(3);
+        // on a fork that gets pruned
+        let bank_4 = bank_1.new_from_parent(4);
+        bank_4.apply(&tx(4));
+        let bank_5 = bank_3.new_from_parent(5);
+        bank_5.apply(&tx(5));
+
+        assert_eq!(bank_5.accounts_db.finality_latency(&tx(1).id()), None);
+
+        bank_3.finalize();
+        let accounts_db = &bank_5.accounts_db;
+        assert_eq!(accounts_db.finality_latency(&tx(1).id()), Some((1, 3)));
+        assert_eq!(accounts_db.finality_latency(&tx(2).id()), Some((2, 3)));
+        assert_eq!(accounts_db.finality_latency(&tx(4).id()), None);
+        assert_eq!(accounts_db.finality_latency(&tx(5).id()), None);
+
+        // the sweep forgets transactions rooted by earlier roots
+        bank_5.finalize();
+        assert_eq!(accounts_db.finality_latency(&tx(1).id()), None);
+        assert_eq!(accounts_db.finality_latency(&tx(4).id()), None);
+        assert_eq!(accounts_db.finality_latency(&tx(5).id()), Some((5, 5)));
+
+        // nothing is recorded unless the bank asks for it
+        let bank = Bank::genesis_bank().new_from_parent(1);
+        bank.apply(&tx(6));
+        bank.finalize();
+        assert_eq!(bank.accounts_db.finality_latency(&tx(6).id()), None);
+    }
+
+    #[test]
+    fn test_apply_batch_best_effort() {
+        let bank = Bank::genesis_bank()
+            .with_conflict_policy(ConflictPolicy::Accumulate)
+            .new_from_parent(1);
+        let tx = |from, to, amount| {
+            Transaction::builder()
+                .from(from)
+                .to(to)
+                .amount(amount)
+                .build()
+                .unwrap()
+        };
+        let txs = [
+            tx(0, 1, 10),
+            tx(0, 2, 20),
+            // 1 only holds 10
+            tx(1, 3, 11),
+            tx(0, 4, 40),
+            tx(0, 5, 50),
+        ];
+
+        assert_eq!(
+            bank.apply_batch_best_effort(&txs),
+            BatchResult {
+                applied: 2,
+                error: Some(TransactionError::InsufficientFunds),
+            }
+        );
+        assert_eq!(bank.get_account(1).unwrap().balance, 10);
+        assert_eq!(bank.get_account(2).unwrap().balance, 20);
+        assert_eq!(bank.get_account(3), None);
+        assert_eq!(bank.get_account(4), None);
+        assert_eq!(bank.get_account(0).unwrap().balance, GENESIS_SUPPLY - 30);
+
+        assert_eq!(
+            bank.apply_batch_best_effort(&txs[3..]),
+            BatchResult {
+                applied: 2,
+                error: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_finalize_with_lockout() {
+        let mut bank = Bank::genesis_bank();
+        for slot in [1, 2, 4, 5] {
+            bank = bank.new_from_parent(slot);
+            bank.transfer(0, slot, slot).unwrap();
+        }
+
+        let report = bank.finalize_with_lockout(2);
+
+        assert_eq!(bank.accounts_db.finalized_slot(), 2);
+        // 0, the recipient and the clock in each of slots 1 and 2
+        assert_eq!(report.promoted, 6);
+        assert_eq!(bank.accounts_db.accounts_modified_in_any(&[1, 2]), vec![]);
+        assert_eq!(
+            bank.accounts_db.accounts_modified_in(4),
+            vec![0, 4, CLOCK_ID]
+        );
+        assert_eq!(
+            bank.accounts_db.accounts_modified_in(5),
+            vec![0, 5, CLOCK_ID]
+        );
+        assert_eq!(bank.get_account(0).unwrap().balance, GENESIS_SUPPLY - 12);
+
+        // nothing left to root once the lockout covers the whole chain
+        assert_eq!(bank.finalize_with_lockout(5), FinalizeReport::default());
+        assert_eq!(bank.accounts_db.finalized_slot(), 2);
+    }
+
+    #[test]
+    fn test_validate() {
+        let bank = Bank::genesis_bank()
+            .with_fee_schedule(FeeSchedule {
+                base_fee: 5,
+                fee_per_priority: 0,
+            })
+            .with_dedup()
+            .new_from_parent(1);
+        let tx = |from, amount| {
+            Transaction::builder()
+                .from(from)
+                .to(2)
+                .amount(amount)
+                .build()
+                .unwrap()
+        };
+
+        assert_eq!(bank.validate(&tx(0, GENESIS_SUPPLY - 5)), Ok(()));
+        assert_eq!(
+            bank.validate(&tx(0, GENESIS_SUPPLY - 4)),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(
+            bank.validate(&tx(1, 1)),
+            Err(TransactionError::InsufficientFunds)
+        );
+        // apply can't lock the same account twice
+        let self_transfer = Transaction::builder()
+            .from(2)
+            .to(2)
+            .amount(0)
+            .build()
+            .unwrap();
+        assert_eq!(
+            bank.validate(&self_transfer),
+            Err(TransactionError::DuplicateAccount(2))
+        );
+        // nothing was locked or written
+        assert_eq!(bank.get_account(2), None);
+        assert!(bank.lock_accounts(&[], &[0, 2]).is_ok());
+
+        bank.apply(&tx(0, 10));
+        assert_eq!(
+            bank.validate(&tx(0, 10)),
+            Err(TransactionError::AlreadyProcessed)
+        );
+    }
+
+    fn apply_twice_in_slot(
+        conflict_policy: ConflictPolicy,
+    ) -> (Bank, Result<(), TransactionError>) {
+        let bank = Bank::genesis_bank()
+            .with_conflict_policy(conflict_policy)
+            .new_from_parent(1);
+        let tx = |amount| {
+            Transaction::builder()
+                .from(0)
+                .to(1)
+                .amount(amount)
+                .build()
+                .unwrap()
+        };
+        bank.apply(&tx(10));
+        let result = bank.apply_and_hash(&tx(5)).map(drop);
+
+        (bank, result)
+    }
+
+    #[test]
+    fn test_conflict_policy_accumulate() {
+        let (bank, result) = apply_twice_in_slot(ConflictPolicy::Accumulate);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(bank.get_account(0).unwrap().balance, GENESIS_SUPPLY - 15);
+        assert_eq!(bank.get_account(1).unwrap().balance, 15);
+    }
+
+    #[test]
+    fn test_conflict_policy_overwrite() {
+        let supply = |bank: &Bank| -> u64 {
+            bank.export_state()
+                .values()
+                .map(|account| account.balance)
+                .sum()
+        };
+
+        let (bank, result) = apply_twice_in_slot(ConflictPolicy::Overwrite);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(bank.get_account(0).unwrap().balance, GENESIS_SUPPLY - 5);
+        assert_eq!(bank.get_account(1).unwrap().balance, 5);
+        assert_eq!(supply(&bank), GENESIS_SUPPLY);
+
+        // rewinding 0 but not 1 would mint the first transfer back
+        let tx = Transaction::builder()
+            .from(0)
+            .to(3)
+            .amount(5)
+            .build()
+            .unwrap();
+        assert_eq!(
+            bank.apply_and_hash(&tx),
+            Err(TransactionError::SlotConflict)
+        );
+        assert_eq!(bank.get_account(1).unwrap().balance, 5);
+        assert_eq!(bank.get_account(3), None);
+        assert_eq!(supply(&bank), GENESIS_SUPPLY);
+
+        // it's the default
+        assert_eq!(
+            Bank::genesis_bank().conflict_policy,
+            ConflictPolicy::Overwrite
+        );
+    }
+
+    #[test]
+    fn test_conflict_policy_reject() {
+        let (bank, result) = apply_twice_in_slot(ConflictPolicy::Reject);
+
+        assert_eq!(result, Err(TransactionError::SlotConflict));
+        assert_eq!(bank.get_account(0).unwrap().balance, GENESIS_SUPPLY - 10);
+        assert_eq!(bank.get_account(1).unwrap().balance, 10);
+
+        // a child slot starts clean and inherits the policy
+        let bank_2 = bank.new_from_parent(2);
+        assert_eq!(bank_2.conflict_policy, ConflictPolicy::Reject);
+        bank_2.transfer(1, 2, 1).unwrap();
+        assert_eq!(
+            bank_2.apply_and_hash(
+                &Transaction::builder()
+                    .from(0)
+                    .to(1)
+                    .amount(1)
+                    .build()
+                    .unwrap()
+            ),
+            Err(TransactionError::SlotConflict)
+        );
+        assert!(bank_2
+            .apply_and_hash(
+                &Transaction::builder()
+                    .from(0)
+                    .to(3)
+                    .amount(1)
+                    .build()
+                    .unwrap()
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_apply_sponsored() {
+        let bank = Bank::genesis_bank()
+            .with_conflict_policy(ConflictPolicy::Accumulate)
+            .with_fee_schedule(FeeSchedule {
+                base_fee: 5,
+                fee_per_priority: 0,
+            });
+        bank.transfer(0, 1, 100).unwrap();
+        bank.transfer(0, 2, 6).unwrap();
+        let tx = |amount| {
+            Transaction::builder()
+                .from(1)
+                .to(3)
+                .amount(amount)
+                .build()
+                .unwrap()
+        };
+        let balances = |bank: &Bank| {
+            [1, 2, 3].map(|account_id| bank.get_account(account_id).map_or(0, |a| a.balance))
+        };
+
+        bank.apply_sponsored(&tx(100), 2).unwrap();
+        assert_eq!(balances(&bank), [0, 1, 100]);
+
+        // the sponsor can't cover a second fee, and a sender paying its own
+        // fee needs it on top of the amount
+        let bank = bank.new_from_parent(1);
+        assert_eq!(
+            bank.apply_sponsored(&tx(0), 2),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(
+            bank.apply_sponsored(
+                &Transaction::builder()
+                    .from(3)
+                    .to(1)
+                    .amount(96)
+                    .build()
+                    .unwrap(),
+                3
+            ),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(balances(&bank), [0, 1, 100]);
+
+        // the fee payer can also be the recipient, paying from what it held
+        // before the transfer
+        bank.transfer(3, 1, 10).unwrap();
+        bank.apply_sponsored(
+            &Transaction::builder()
+                .from(3)
+                .to(1)
+                .amount(50)
+                .build()
+                .unwrap(),
+            1,
+        )
+        .unwrap();
+        assert_eq!(balances(&bank), [55, 1, 40]);
+    }
+
+    #[test]
+    fn test_balance_trajectory() {
+        let bank_0 = Bank::genesis_bank();
+        let bank_1 = bank_0.new_from_parent(1);
+        bank_1.transfer(0, 1, 10).unwrap();
+        let bank_2 = bank_1.new_from_parent(2);
+        // a write that leaves the balance alone isn't a change
+        bank_2.transfer(1, 2, 0).unwrap();
+        bank_2.transfer(0, 2, 1).unwrap();
+        let bank_3 = bank_2.new_from_parent(3);
+        bank_3.transfer(0, 1, 5).unwrap();
+        let bank_4 = bank_3.new_from_parent(4);
+
+        assert_eq!(bank_4.balance_trajectory(1), vec![(1, 10), (3, 15)]);
+        assert_eq!(
+            bank_4.balance_trajectory(0),
+            vec![
+                (1, GENESIS_SUPPLY - 10),
+                (2, GENESIS_SUPPLY - 11),
+                (3, GENESIS_SUPPLY - 16)
+            ]
+        );
+        assert_eq!(bank_2.balance_trajectory(1), vec![(1, 10)]);
+        assert_eq!(bank_4.balance_trajectory(9), vec![]);
+    }
+
+    #[test]
+    fn test_transfer_if() {
+        let bank = Bank::genesis_bank();
+        bank.transfer(0, 3, 50).unwrap();
+        let bank = bank.new_from_parent(1);
+
+        assert_eq!(bank.transfer_if(0, 1, 10, 3, 51), Ok(false));
+        assert_eq!(bank.get_account(1), None);
+        assert_eq!(bank.get_account(0).unwrap().balance, GENESIS_SUPPLY - 50);
+
+        assert_eq!(bank.transfer_if(0, 1, 10, 3, 50), Ok(true));
+        assert_eq!(bank.get_account(1).unwrap().balance, 10);
+        // the condition can be one of the accounts being written
+        assert_eq!(bank.transfer_if(1, 2, 10, 1, 11), Ok(false));
+        assert_eq!(bank.transfer_if(1, 2, 10, 1, 10), Ok(true));
+        assert_eq!(bank.get_account(2).unwrap().balance, 10);
+
+        // a failed transfer creates neither side
+        let account_count = bank.account_count();
+        assert_eq!(
+            bank.transfer_if(4, 5, 10, 3, 0),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(bank.get_account(4), None);
+        assert_eq!(bank.get_account(5), None);
+        assert_eq!(bank.account_count(), account_count);
+    }
+
+    #[test]
+    fn test_extend_chain() {
+        let bank_0 = Bank::genesis_bank();
+        bank_0.transfer(0, 1, 10).unwrap();
+
+        let tip = bank_0.extend_chain(100);
+
+        assert_eq!(tip.slot, 100);
+        assert_eq!(tip.ancestors, (0..=100).collect::<Vec<_>>());
+        assert_eq!(tip.clock(), 100);
+        assert_eq!(tip.get_account(0).unwrap().balance, GENESIS_SUPPLY - 10);
+        assert_eq!(tip.get_account(1).unwrap().balance, 10);
+        assert_eq!(tip.extend_chain(0).ancestors, tip.ancestors);
+    }
+
+    #[test]
+    fn test_write_allowlist() {
+        let bank = Bank::genesis_bank()
+            .with_write_allowlist([0, 1])
+            .new_from_parent(1);
+        let tx = |to| {
+            Transaction::builder()
+                .from(0)
+                .to(to)
+                .amount(10)
+                .build()
+                .unwrap()
+        };
+
+        assert_eq!(
+            bank.apply_and_hash(&tx(2)),
+            Err(TransactionError::WriteNotPermitted)
+        );
+        assert_eq!(
+            bank.validate(&tx(2)),
+            Err(TransactionError::WriteNotPermitted)
+        );
+        assert_eq!(bank.get_account(2), None);
+        assert_eq!(bank.get_account(0).unwrap().balance, GENESIS_SUPPLY);
+        assert_eq!(
+            bank.apply_sponsored(&tx(1), 3),
+            Err(TransactionError::WriteNotPermitted)
+        );
+
+        bank.apply(&tx(1));
+        assert_eq!(bank.get_account(1).unwrap().balance, 10);
+    }
+
+    #[test]
+    #[cfg(feature = "debug-invariants")]
+    #[should_panic(expected = "changed the total balances")]
+    fn test_debug_invariants() {
+        // a transfer that forgets to debit, standing in for bugs like a
+        // self-transfer crediting from a stale copy of the account
+        struct LeakyTransfer;
+        impl Executor for LeakyTransfer {
+            fn execute(
+                &self,
+                tx: &Transaction,
+                accounts: &mut [&mut Account],
+            ) -> Result<(), TransactionError> {
+                accounts[1].balance += tx.amount;
+
+                Ok(())
+            }
+
+            fn conserves_balance(&self) -> bool {
+                true
+            }
+        }
+
+        let bank = Bank::genesis_bank().with_fee_schedule(FeeSchedule {
+            base_fee: 1,
+            fee_per_priority: 0,
+        });
+        let tx = Transaction::builder()
+            .from(0)
+            .to(1)
+            .amount(5)
+            .build()
+            .unwrap();
+        // fees are burned, and that's accounted for
+        bank.apply(&tx);
+
+        bank.with_executor(Arc::new(LeakyTransfer)).apply(&tx);
+    }
+
+    #[test]
+    fn test_metadata() {
+        let bank_0 = Bank::genesis_bank().with_metadata("leader", "validator3");
+        let bank_1 = bank_0
+            .new_from_parent(1)
+            .with_metadata("leader", "validator1");
+        let bank_2 = bank_1.new_from_parent(2).with_metadata("note", "test");
+
+        assert_eq!(bank_0.metadata["leader"], "validator3");
+        assert_eq!(bank_1.metadata["leader"], "validator1");
+        assert_eq!(
+            bank_2.metadata,
+            HashMap::from([
+                ("leader".to_string(), "validator1".to_string()),
+                ("note".to_string(), "test".to_string()),
+            ])
+        );
+        assert_eq!(
+            bank_2.state_hash(),
+            Bank::genesis_bank().extend_chain(2).state_hash()
+        );
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let bank = Bank::genesis_bank();
+        bank.transfer(0, 1, 10).unwrap();
+        let root = bank.merkle_root();
+        assert_eq!(bank.merkle_root(), root);
+
+        // the clock isn't a leaf
+        let bank_1 = bank.new_from_parent(1);
+        assert_eq!(bank_1.merkle_root(), root);
+        let root_1 = bank_1.merkle_root();
+
+        // data isn't a leaf, balances are
+        bank_1.realloc(1, 4).unwrap();
+        assert_eq!(bank_1.merkle_root(), root_1);
+        bank_1.transfer(1, 0, 1).unwrap();
+        assert_ne!(bank_1.merkle_root(), root_1);
+        bank_1.transfer(0, 1, 1).unwrap();
+        assert_eq!(bank_1.merkle_root(), root_1);
+        assert_eq!(bank.merkle_root(), root);
+    }
+
+    #[test]
+    fn test_assert_forks_isolated() {
+        let bank_0 = Bank::genesis_bank();
+        bank_0.transfer(0, 1, 100).unwrap();
+        let siblings: Vec<Bank> = (1..=3).map(|slot| bank_0.new_from_parent(slot)).collect();
+        for (amount, bank) in (1..).zip(&siblings) {
+            bank.transfer(1, 2, amount).unwrap();
+        }
+        let bank_4 = siblings[1].new_from_parent(4);
+
+        let banks: Vec<&Bank> = [&bank_0, &bank_4].into_iter().chain(&siblings).collect();
+        for account_id in 0..3 {
+            assert_forks_isolated(&banks, account_id);
+        }
+        for (amount, bank) in (1..).zip(&siblings) {
+            assert_eq!(bank.get_account(2).unwrap().balance, amount);
+        }
+        assert_eq!(bank_4.get_account(1).unwrap().balance, 98);
+
+        siblings[0].finalize();
+        assert_forks_isolated(&[&siblings[0]], 1);
+    }
+
+    #[test]
+    fn test_state_hash_covers_data() {
+        let bank_with_data = |data: Vec<u8>| {
+            let bank = Bank::genesis_bank().new_from_parent(1);
+            bank.transfer(0, 1, 10).unwrap();
+            {
+                let (_, mut write_accounts) =
+                    bank.accounts_db.load_versioned_accounts(&[], &[1]).unwrap();
+                write_accounts[0]
+                    .load_account(&bank.ancestors)
+                    .unwrap()
+                    .data = data;
+            }
+            bank
+        };
+
+        let bank = bank_with_data(vec![1, 2]);
+        assert_eq!(bank.state_hash(), bank_with_data(vec![1, 2]).state_hash());
+        assert_ne!(bank.state_hash(), bank_with_data(vec![1, 3]).state_hash());
+        assert_ne!(bank.state_hash(), bank_with_data(vec![]).state_hash());
+    }
+
+    #[test]
+    fn test_export_state() {
+        let bank_0 = Bank::genesis_bank();
+        bank_0.pay_many(0, &[(1, 10), (2, 20)]).unwrap();
+        let bank_1 = bank_0.new_from_parent(1);
+        bank_1.transfer(2, 3, 5).unwrap();
+
+        let exported = bank_1.export_state();
+
+        assert_eq!(
+            exported.keys().copied().collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        for (account_id, account) in &exported {
+            assert_eq!(bank_1.get_account(*account_id).as_ref(), Some(account));
+        }
+        assert_eq!(bank_0.export_state().len(), 3);
+    }
+
+    #[test]
+    fn test_divergence_slot() {
+        let bank_0 = Bank::genesis_bank();
+        let bank_1 = bank_0.new_from_parent(1);
+        let bank_2 = bank_1.new_from_parent(2);
+        let bank_3 = bank_1.new_from_parent(3);
+        let bank_4 = bank_3.new_from_parent(4);
+        let bank_5 = bank_2.new_from_parent(5);
+
+        let finalized_chain = &bank_2.ancestors;
+        assert_eq!(bank_0.divergence_slot(finalized_chain), None);
+        assert_eq!(bank_2.divergence_slot(finalized_chain), None);
+        assert_eq!(bank_5.divergence_slot(finalized_chain), None);
+        assert_eq!(bank_3.divergence_slot(finalized_chain), Some(3));
+        assert_eq!(bank_4.divergence_slot(finalized_chain), Some(3));
+    }
+
+    #[test]
+    fn test_realloc_grow() {
+        let bank = Bank::genesis_bank()
+            .with_rent(Rent { per_byte: 2 })
+            .new_from_parent(1);
+        bank.transfer(0, 1, 20).unwrap();
+
+        bank.realloc(1, 4).unwrap();
+        assert_eq!(bank.get_account(1).unwrap().data, vec![0; 4]);
+        bank.realloc(1, 10).unwrap();
+        assert_eq!(bank.get_account(1).unwrap().data, vec![0; 10]);
+
+        // 11 bytes needs 22 of rent
+        assert_eq!(
+            bank.realloc(1, 11),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(
+            bank.realloc(0, MAX_DATA_LEN + 1),
+            Err(TransactionError::DataTooLarge)
+        );
+        assert_eq!(bank.get_account(1).unwrap().data.len(), 10);
+    }
+
+    #[test]
+    fn test_realloc_failed_grow_leaves_account_untouched() {
+        let bank = Bank::genesis_bank().new_from_parent(1);
+        let account_count = bank.account_count();
+        let dirty_accounts = bank.dirty_accounts();
+
+        assert_eq!(
+            bank.realloc(9, MAX_DATA_LEN + 1),
+            Err(TransactionError::DataTooLarge)
+        );
+        assert_eq!(bank.get_account(9), None);
+        assert_eq!(bank.account_count(), account_count);
+        assert_eq!(bank.dirty_accounts(), dirty_accounts);
+    }
+
+    #[test]
+    fn test_realloc_shrink() {
+        let bank = Bank::genesis_bank().new_from_parent(1);
+        bank.transfer(0, 1, 20).unwrap();
+        {
+            let (_, mut write_accounts) =
+                bank.accounts_db.load_versioned_accounts(&[], &[1]).unwrap();
+            write_accounts[0]
+                .load_account(&bank.ancestors)
+                .unwrap()
+                .data = vec![1, 2, 3, 4];
+        }
+
+        // shrinking doesn't need rent, even on a bank that now charges it
+        let bank = bank.with_rent(Rent { per_byte: 100 });
+        bank.realloc(1, 2).unwrap();
+        assert_eq!(bank.get_account(1).unwrap().data, vec![1, 2]);
+        bank.realloc(1, 0).unwrap();
+        assert_eq!(bank.get_account(1).unwrap().data, Vec::<u8>::new());
+        assert_eq!(bank.get_account(1).unwrap().balance, 20);
+    }
+
+    #[test]
+    fn test_transaction_bytes_round_trip() {
+        let tx = Transaction {
+            from: 1,
+            to: u64::MAX,
+            amount: 42,
+            token: 7,
+            nonce: 3,
+            priority: 9,
+        };
+
+        let bytes = tx.to_bytes();
+        assert_eq!(bytes.len(), Transaction::ENCODED_LEN);
+        assert_eq!(&bytes[16..24], &42u64.to_le_bytes());
+        assert_eq!(Transaction::from_bytes(&bytes), Ok(tx));
+    }
+
+    #[test]
+    fn test_transaction_hash_set() {
+        let tx = |amount| {
+            Transaction::builder()
+                .from(0)
+                .to(1)
+                .amount(amount)
+                .build()
+                .unwrap()
+        };
+
+        let txs: HashSet<Transaction> = [tx(1), tx(2), tx(1)].into_iter().collect();
+
+        assert_eq!(txs.len(), 2);
+        assert!(txs.contains(&tx(1)));
+        assert!(txs.contains(&tx(2)));
+        assert!(!txs.contains(&tx(3)));
+    }
+
+    #[test]
+    fn test_transaction_from_short_bytes() {
+        let bytes = Transaction::builder()
+            .from(0)
+            .to(1)
+            .amount(1)
+            .build()
+            .unwrap()
+            .to_bytes();
+
+        assert_eq!(
+            Transaction::from_bytes(&bytes[..47]),
+            Err(DecodeError::WrongLength(47))
+        );
+        assert_eq!(
+            Transaction::from_bytes(&[]),
+            Err(DecodeError::WrongLength(0))
+        );
+    }
+
+    #[test]
+    fn test_simulate_batch() {
+        let bank = Bank::genesis_bank()
+            .with_conflict_policy(ConflictPolicy::Accumulate)
+            .with_fee_schedule(FeeSchedule {
+                base_fee: 1,
+                fee_per_priority: 0,
+            })
+            .new_from_parent(1);
+        let tx = |from, to, amount| {
+            Transaction::builder()
+                .from(from)
+                .to(to)
+                .amount(amount)
+                .build()
+                .unwrap()
+        };
+        let account = |balance| Account {
+            balance,
+            ..Default::default()
+        };
+
+        let results = bank.simulate_batch(&[tx(0, 1, 100), tx(1, 2, 60), tx(1, 2, 60)]);
+
+        assert_eq!(
+            results,
+            vec![
+                Ok(SimulationResult {
+                    fee: 1,
+                    accounts: vec![(0, account(GENESIS_SUPPLY - 101)), (1, account(100))],
+                }),
+                // only possible because of the first transaction
+                Ok(SimulationResult {
+                    fee: 1,
+                    accounts: vec![(1, account(39)), (2, account(60))],
+                }),
+                Err(TransactionError::InsufficientFunds),
+            ]
+        );
+        // nothing was written
+        assert_eq!(bank.get_account(0), Some(account(GENESIS_SUPPLY)));
+        assert_eq!(bank.get_account(1), None);
+        assert_eq!(bank.dirty_accounts(), vec![]);
+        assert_eq!(
+            bank.simulate(&tx(1, 2, 1)),
+            Err(TransactionError::InsufficientFunds)
+        );
+
+        // a self-transfer is rejected rather than run against two copies of
+        // the account, the same as applying it
+        assert_eq!(
+            bank.simulate(&tx(0, 0, 5)),
+            Err(TransactionError::DuplicateAccount(0))
+        );
+        assert_eq!(
+            bank.apply_at_slot(&tx(0, 0, 5), 1),
+            Err(TransactionError::DuplicateAccount(0))
+        );
+        assert_eq!(bank.get_account(0), Some(account(GENESIS_SUPPLY)));
+    }
+
+    #[test]
+    fn test_simulate_batch_runs_apply_checks() {
+        let tx = |from, to, amount| {
+            Transaction::builder()
+                .from(from)
+                .to(to)
+                .amount(amount)
+                .build()
+                .unwrap()
+        };
+        let bank_0 = Bank::genesis_bank();
+        bank_0.transfer(0, 1, 50).unwrap();
+
+        // dedup covers what already landed and what's earlier in the batch
+        let bank = bank_0
+            .clone()
+            .with_conflict_policy(ConflictPolicy::Accumulate)
+            .with_dedup()
+            .new_from_parent(1);
+        bank.apply(&tx(0, 1, 1));
+        let results = bank.simulate_batch(&[tx(0, 1, 1), tx(1, 2, 1), tx(1, 2, 1)]);
+        assert_eq!(results[0], Err(TransactionError::AlreadyProcessed));
+        assert!(results[1].is_ok());
+        assert_eq!(results[2], Err(TransactionError::AlreadyProcessed));
+
+        // pinned accounts and the write allowlist
+        let bank = bank_0
+            .clone()
+            .with_write_allowlist([0, 1])
+            .new_from_parent(2);
+        assert_eq!(
+            bank.simulate(&tx(0, CLOCK_ID, 1)),
+            Err(TransactionError::ImmutableAccount)
+        );
+        assert_eq!(
+            bank.simulate(&tx(0, 2, 1)),
+            Err(TransactionError::WriteNotPermitted)
+        );
+
+        // the conflict policy, against the slot and earlier in the batch
+        let bank = bank_0
+            .clone()
+            .with_conflict_policy(ConflictPolicy::Reject)
+            .new_from_parent(3);
+        bank.apply(&tx(1, 2, 1));
+        let results = bank.simulate_batch(&[tx(1, 3, 1), tx(0, 4, 1), tx(4, 5, 1)]);
+        assert_eq!(results[0], Err(TransactionError::SlotConflict));
+        assert!(results[1].is_ok());
+        assert_eq!(results[2], Err(TransactionError::SlotConflict));
+
+        let bank = bank_0.clone().new_from_parent(4);
+        bank.apply(&tx(1, 2, 10));
+        let results = bank.simulate_batch(&[tx(1, 2, 5), tx(1, 3, 5)]);
+        // starts from the parent slot, replacing the applied transfer
+        assert_eq!(
+            results[0].as_ref().unwrap().accounts,
+            vec![
+                (
+                    1,
+                    Account {
+                        balance: 45,
+                        ..Default::default()
+                    }
+                ),
+                (
+                    2,
+                    Account {
+                        balance: 5,
+                        ..Default::default()
+                    }
+                ),
+            ]
+        );
+        assert_eq!(results[1], Err(TransactionError::SlotConflict));
+
+        // and nothing gets past a frozen bank
+        bank.freeze();
+        assert_eq!(
+            bank.simulate(&tx(0, 1, 1)),
+            Err(TransactionError::BankFrozen)
+        );
+    }
+
+    #[test]
+    fn test_failed_apply_leaves_accounts_unchanged() {
+        let bank = Bank::genesis_bank()
+            .with_conflict_policy(ConflictPolicy::Accumulate)
+            .with_fee_schedule(FeeSchedule {
+                base_fee: 1,
+                fee_per_priority: 0,
+            })
+            .new_from_parent(1);
+        bank.transfer(0, 1, 10).unwrap();
+        let account_count = bank.account_count();
+        let state_hash = bank.state_hash();
+
+        let tx = Transaction::builder()
+            .from(1)
+            .to(2)
+            .amount(10)
+            .build()
+            .unwrap();
+        assert_eq!(
+            bank.apply_at_slot(&tx, 1),
+            Err(TransactionError::InsufficientFunds)
+        );
+        // the fee wasn't charged either, and the recipient wasn't created
+        assert_eq!(bank.get_account(1).unwrap().balance, 10);
+        assert_eq!(bank.get_account(2), None);
+        assert_eq!(bank.account_count(), account_count);
+        assert_eq!(bank.state_hash(), state_hash);
+    }
 }
@@ -0,0 +1,105 @@
+//! Pending transactions waiting to be scheduled. Each transaction is held at
+//! most once, keyed by `Transaction::id`, and batches come out highest
+//! priority first, oldest first among equal priorities. A popped batch is
+//! what `scheduler::schedule` expects as input.
+
+use super::*;
+
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+
+#[derive(Default)]
+pub struct Mempool {
+    transactions: HashMap<TransactionId, (u64, Transaction)>,
+    // (priority, insertion order, id), in the order transactions leave
+    queue: BTreeSet<(Reverse<u64>, u64, TransactionId)>,
+    inserted: u64,
+}
+
+impl Mempool {
+    /// Add `tx`, returning `false` if a transaction with the same id is
+    /// already pending.
+    pub fn insert(&mut self, tx: Transaction) -> bool {
+        let id = tx.id();
+        if self.transactions.contains_key(&id) {
+            return false;
+        }
+
+        let order = self.inserted;
+        self.inserted += 1;
+        self.queue.insert((Reverse(tx.priority), order, id));
+        self.transactions.insert(id, (order, tx));
+
+        true
+    }
+
+    /// Take up to `n` transactions, highest priority first.
+    pub fn pop_batch(&mut self, n: usize) -> Vec<Transaction> {
+        let mut batch = Vec::with_capacity(n.min(self.len()));
+        while batch.len() < n {
+            let Some((_, _, id)) = self.queue.pop_first() else {
+                break;
+            };
+            let (_, tx) = self
+                .transactions
+                .remove(&id)
+                .expect("queued ids are pending");
+            batch.push(tx);
+        }
+
+        batch
+    }
+
+    pub fn remove(&mut self, id: &TransactionId) -> Option<Transaction> {
+        let (order, tx) = self.transactions.remove(id)?;
+        self.queue.remove(&(Reverse(tx.priority), order, *id));
+
+        Some(tx)
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(from: AccountId, priority: u64) -> Transaction {
+        Transaction::builder()
+            .from(from)
+            .to(100)
+            .amount(1)
+            .priority(priority)
+            .build()
+            .expect("build")
+    }
+
+    #[test]
+    fn test_mempool() {
+        let mut mempool = Mempool::default();
+
+        assert!(mempool.insert(tx(1, 5)));
+        assert!(mempool.insert(tx(2, 9)));
+        assert!(!mempool.insert(tx(1, 5)));
+        assert!(mempool.insert(tx(3, 5)));
+        assert!(mempool.insert(tx(4, 1)));
+        assert!(!mempool.insert(tx(2, 9)));
+        assert_eq!(mempool.len(), 4);
+
+        assert_eq!(mempool.remove(&tx(4, 1).id()), Some(tx(4, 1)));
+        assert_eq!(mempool.remove(&tx(4, 1).id()), None);
+        // removed transactions can be inserted again
+        assert!(mempool.insert(tx(4, 1)));
+
+        assert_eq!(mempool.pop_batch(2), vec![tx(2, 9), tx(1, 5)]);
+        assert_eq!(mempool.pop_batch(5), vec![tx(3, 5), tx(4, 1)]);
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.pop_batch(1), vec![]);
+    }
+}
@@ -0,0 +1,112 @@
+//! A view of a `Bank` that can only query it, for handing to code that
+//! mustn't apply transactions or otherwise write.
+
+use super::*;
+
+/// Only queries are exposed, so there's no `apply`:
+///
+/// ```compile_fail
+/// use smolchain::{Bank, Transaction};
+///
+/// let bank = Bank::genesis_bank();
+/// bank.as_readonly().apply(&Transaction {
+///     from: 0,
+///     to: 1,
+///     amount: 10,
+///     token: 0,
+///     nonce: 0,
+///     priority: 0,
+/// });
+/// ```
+///
+/// rustdoc doesn't run doctests for a binary crate, so
+/// `test_read_only_bank_has_no_apply` checks the same thing at compile time.
+pub struct ReadOnlyBank<'a> {
+    bank: &'a Bank,
+}
+
+impl Bank {
+    pub fn as_readonly(&self) -> ReadOnlyBank<'_> {
+        ReadOnlyBank { bank: self }
+    }
+}
+
+impl ReadOnlyBank<'_> {
+    pub fn slot(&self) -> Slot {
+        self.bank.slot
+    }
+
+    pub fn ancestors(&self) -> &[Slot] {
+        &self.bank.ancestors
+    }
+
+    pub fn get_account(&self, account_id: AccountId) -> Option<Account> {
+        self.bank.get_account(account_id)
+    }
+
+    /// The native balance of `account_id`, or `None` if it doesn't exist on
+    /// this fork.
+    pub fn balance(&self, account_id: AccountId) -> Option<u64> {
+        self.get_account(account_id).map(|account| account.balance)
+    }
+
+    pub fn clock(&self) -> Slot {
+        self.bank.clock()
+    }
+
+    pub fn account_count(&self) -> u64 {
+        self.bank.account_count()
+    }
+
+    pub fn accounts_iter(&self) -> impl Iterator<Item = (AccountId, Account)> {
+        self.bank.accounts_iter()
+    }
+
+    pub fn accounts_by_owner(&self, owner: AccountId) -> Vec<(AccountId, Account)> {
+        self.bank.accounts_by_owner(owner)
+    }
+
+    pub fn state_hash(&self) -> [u8; 32] {
+        self.bank.state_hash()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_only_bank() {
+        let bank_0 = Bank::genesis_bank();
+        let bank_1 = bank_0.new_from_parent(1);
+        bank_1.transfer(0, 1, 10).unwrap();
+
+        // there's no way to reach `apply` or the db through the view, so
+        // it can only be used to query
+        let read_only = bank_1.as_readonly();
+        assert_eq!(read_only.slot(), 1);
+        assert_eq!(read_only.ancestors(), &[0, 1]);
+        assert_eq!(read_only.balance(0), Some(GENESIS_SUPPLY - 10));
+        assert_eq!(read_only.get_account(1), bank_1.get_account(1));
+        assert_eq!(read_only.balance(2), None);
+        assert_eq!(read_only.clock(), 1);
+        assert_eq!(read_only.state_hash(), bank_1.state_hash());
+        assert_eq!(bank_0.as_readonly().balance(1), None);
+    }
+
+    #[test]
+    fn test_read_only_bank_has_no_apply() {
+        struct NotApplied;
+        trait NoApply {
+            fn apply(&self) -> NotApplied {
+                NotApplied
+            }
+        }
+        impl NoApply for ReadOnlyBank<'_> {}
+
+        // inherent methods shadow trait ones, so this stops compiling if
+        // `ReadOnlyBank` grows an `apply` of its own
+        let bank = Bank::genesis_bank();
+        let NotApplied = bank.as_readonly().apply();
+    }
+}
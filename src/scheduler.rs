@@ -0,0 +1,77 @@
+//! Groups transactions into batches whose members touch disjoint accounts and
+//! can therefore be applied in parallel. Batches are meant to run one after
+//! another.
+//!
+//! Transactions are placed in descending priority order, each into the
+//! earliest batch after the last one it conflicts with, going by
+//! `Transaction::lock_set`: two transactions conflict if either writes an
+//! account the other reads or writes. So among transactions that conflict,
+//! higher priority ones always run first.
+
+use super::*;
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
+pub fn schedule(txs: &[Transaction]) -> Vec<Vec<&Transaction>> {
+    let mut by_priority: Vec<&Transaction> = txs.iter().collect();
+    by_priority.sort_by_key(|tx| Reverse(tx.priority));
+
+    // (read, write) locks taken by each batch
+    let mut locked_accounts: Vec<(HashSet<AccountId>, HashSet<AccountId>)> = Vec::new();
+    let mut batches: Vec<Vec<&Transaction>> = Vec::new();
+
+    for tx in by_priority {
+        let (reads, writes) = tx.lock_set();
+
+        let batch = locked_accounts
+            .iter()
+            .rposition(|(locked_reads, locked_writes)| {
+                reads.iter().any(|account| locked_writes.contains(account))
+                    || writes.iter().any(|account| {
+                        locked_reads.contains(account) || locked_writes.contains(account)
+                    })
+            })
+            .map_or(0, |conflicting_batch| conflicting_batch + 1);
+
+        if batch == batches.len() {
+            locked_accounts.push(Default::default());
+            batches.push(Vec::new());
+        }
+
+        locked_accounts[batch].0.extend(reads);
+        locked_accounts[batch].1.extend(writes);
+        batches[batch].push(tx);
+    }
+
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(from: AccountId, to: AccountId, priority: u64) -> Transaction {
+        Transaction::builder()
+            .from(from)
+            .to(to)
+            .amount(1)
+            .priority(priority)
+            .build()
+            .expect("build")
+    }
+
+    #[test]
+    fn test_schedule_conflicting_by_priority() {
+        let txs = [tx(0, 1, 1), tx(1, 2, 5), tx(3, 4, 0)];
+
+        let batches = schedule(&txs);
+        let priorities: Vec<Vec<u64>> = batches
+            .iter()
+            .map(|batch| batch.iter().map(|tx| tx.priority).collect())
+            .collect();
+
+        // 1 -> 2 outranks 0 -> 1, and 3 -> 4 doesn't conflict with either
+        assert_eq!(priorities, vec![vec![5, 0], vec![1]]);
+    }
+}